@@ -1,9 +1,16 @@
 fn main() {
-    let output = std::process::Command::new("git")
+    // builds from a release archive or crates.io have no git checkout to ask
+    let git_hash = std::process::Command::new("git")
         .args(["rev-parse", "HEAD"])
         .output()
-        .expect("Failed to get git hash");
-    
-    let git_hash = String::from_utf8(output.stdout).unwrap();
-    println!("cargo:rustc-env=GIT_HASH={}", git_hash.trim());
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=GIT_SHORT_HASH={}", &git_hash[..git_hash.len().min(7)]);
+    println!("cargo:rustc-env=BUILD_DATE={}", chrono::Utc::now().format("%Y-%m-%d"));
 }
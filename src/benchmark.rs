@@ -0,0 +1,61 @@
+use std::{path::Path, time::{Duration, Instant}};
+
+use anyhow::{bail, Context};
+use tabled::Tabled;
+
+/// min / median / max over every cycle for one phase
+#[derive(Tabled)]
+pub struct PhaseStats {
+    pub phase: &'static str,
+    #[tabled(display = "display_duration")]
+    pub min: Duration,
+    #[tabled(display = "display_duration")]
+    pub median: Duration,
+    #[tabled(display = "display_duration")]
+    pub max: Duration,
+}
+
+fn display_duration(duration: &Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+fn stats(phase: &'static str, mut samples: Vec<Duration>) -> PhaseStats {
+    samples.sort();
+    PhaseStats {
+        phase,
+        min: samples[0],
+        median: samples[samples.len() / 2],
+        max: samples[samples.len() - 1],
+    }
+}
+
+/// runs the same build + self match as `run`, `args.cycles` times
+pub async fn benchmark(args: crate::Benchmark, root: &Path) -> anyhow::Result<Vec<PhaseStats>> {
+    if args.cycles == 0 {
+        bail!("--cycles must be at least 1");
+    }
+
+    let run_path = crate::script_path(root, "run")?;
+    // benchmark gamelogs are thrown away instead of piling up in logs/
+    let log_dir = tempfile::tempdir().context("failed to create temp dir")?;
+
+    let mut builds = Vec::new();
+    let mut matches = Vec::new();
+    for cycle in 1..=args.cycles {
+        println!("cycle {}/{}", cycle, args.cycles);
+
+        let start = Instant::now();
+        crate::build_bot(root, true, &[]).await?;
+        builds.push(start.elapsed());
+
+        let log_path = log_dir.path().join(format!("{}.mmgl", cycle));
+        let engine_args = crate::match_args(run_path.clone(), run_path.clone(), Some(log_path), false);
+        let start = Instant::now();
+        mm_engine::engine::run(engine_args)
+            .await
+            .with_context(|| "fatal engine error")?;
+        matches.push(start.elapsed());
+    }
+
+    Ok(vec![stats("build", builds), stats("match", matches)])
+}
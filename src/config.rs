@@ -1,27 +1,168 @@
 use std::{
+    collections::BTreeMap,
+    env,
     fmt::Display,
     fs, 
-    path::Path
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Command,
 };
 
-use anyhow::Context;
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use colored::Colorize;
 use serde::{ Serialize, Deserialize };
+use tabled::Tabled;
+
+const DEFAULT_LOG_NAME: &str = "log-{timestamp}.mmgl";
+const DEFAULT_MAX_SUBMISSION_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_UPDATE_CHECK_TIMEOUT: u64 = 5;
+/// points the cli at another backend (staging, local) without editing the config
+pub const API_URL_ENV: &str = "MM_API_URL";
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub language: Lang,
     #[serde(rename = "api-url")]
     pub api_url: String,
+    /// gamelog file name template, e.g. "{language}-{date}-{time}.mmgl" or "{bot-a}-vs-{bot-b}-{timestamp}.mmgl"
+    #[serde(rename = "log-name", default, skip_serializing_if = "Option::is_none")]
+    pub log_name: Option<String>,
+    /// store the login token in the OS keychain instead of a plaintext file
+    #[serde(rename = "use-keychain", default, skip_serializing_if = "std::ops::Not::not")]
+    pub use_keychain: bool,
+    /// strategy folder relative to the project root, overrides the language default
+    #[serde(rename = "strategy-path", default, skip_serializing_if = "Option::is_none")]
+    pub strategy_path: Option<PathBuf>,
+    /// refuse to upload strategy archives larger than this many bytes (compressed), 10 MB by default
+    #[serde(rename = "max-submission-size", alias = "max-upload-size", default, skip_serializing_if = "Option::is_none")]
+    pub max_submission_size: Option<u64>,
+    /// gzip the submit request body, falling back to plain json if the server refuses it
+    #[serde(rename = "gzip-uploads", default, skip_serializing_if = "std::ops::Not::not")]
+    pub gzip_uploads: bool,
+    /// upload the archive as the raw request body instead of base64 inside json,
+    /// for servers that support it
+    #[serde(rename = "raw-uploads", default, skip_serializing_if = "std::ops::Not::not")]
+    pub raw_uploads: bool,
+    /// also leave the project's `.gitignore` matches out of submissions, off by
+    /// default since templates often ignore folders like `lib/` or `build/`
+    #[serde(rename = "use-gitignore", default, skip_serializing_if = "std::ops::Not::not")]
+    pub use_gitignore: bool,
+    /// seconds `run` waits on its update check before playing anyway, 5 by default
+    #[serde(rename = "update-check-timeout", default, skip_serializing_if = "Option::is_none")]
+    pub update_check_timeout: Option<u64>,
+    /// extra headers sent with every request, for deployments behind a gateway
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// `--api-url` or `$MM_API_URL` replaced the file's api-url. tokens saved per
+    /// project predate per-server tokens and belong to the file's server only
+    #[serde(skip)]
+    pub api_url_overridden: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+impl Config {
+    /// the strategy folder relative to the project root
+    pub fn strategy_path(&self) -> PathBuf {
+        match &self.strategy_path {
+            Some(path) => path.clone(),
+            None => PathBuf::from(self.language.default_strategy_path()),
+        }
+    }
+
+    /// the configured upload limit, or the default when unset
+    pub fn max_submission_size(&self) -> u64 {
+        self.max_submission_size.unwrap_or(DEFAULT_MAX_SUBMISSION_SIZE)
+    }
+
+    /// the configured update check timeout, or the default when unset
+    pub fn update_check_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.update_check_timeout.unwrap_or(DEFAULT_UPDATE_CHECK_TIMEOUT))
+    }
+
+    /// expands the `log-name` template (or the default) into a gamelog file name,
+    /// `bot_a` and `bot_b` name the two sides, e.g. "self" or "version-3"
+    pub fn log_file_name(&self, now: DateTime<Utc>, bot_a: &str, bot_b: &str) -> anyhow::Result<String> {
+        let template = self.log_name.as_deref().unwrap_or(DEFAULT_LOG_NAME);
+
+        let mut name = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            name.push_str(&rest[..start]);
+            let len = rest[start..]
+                .find('}')
+                .with_context(|| format!("unclosed placeholder in log-name '{}'", template))?;
+            match &rest[start + 1..start + len] {
+                "timestamp" => name.push_str(&now.format("%Y%m%d_%H%M%S").to_string()),
+                "date" => name.push_str(&now.format("%Y%m%d").to_string()),
+                "time" => name.push_str(&now.format("%H%M%S").to_string()),
+                "language" => name.push_str(&self.language.to_string()),
+                "bot-a" => name.push_str(bot_a),
+                "bot-b" => name.push_str(bot_b),
+                other => bail!(
+                    "unknown placeholder '{{{}}}' in log-name, expected one of {{timestamp}}, {{date}}, {{time}}, {{language}}, {{bot-a}}, {{bot-b}}",
+                    other
+                ),
+            }
+            rest = &rest[start + len + 1..];
+        }
+        name.push_str(rest);
+
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            bail!("log-name '{}' must expand to a plain file name", template);
+        }
+
+        Ok(name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
 pub enum Lang {
     #[serde(rename = "rust")]
     Rust,
     #[serde(rename = "python")]
     Python,
     #[serde(rename = "java")]
-    Java
+    Java,
+    #[serde(rename = "cpp")]
+    Cpp,
+}
+
+impl Lang {
+    /// guesses the language from the files a starterpack ships with
+    pub fn detect(root: &Path) -> Option<Lang> {
+        if root.join("Cargo.toml").is_file() {
+            Some(Lang::Rust)
+        } else if root.join("pyproject.toml").is_file() || root.join("requirements.txt").is_file() {
+            Some(Lang::Python)
+        } else if root.join("pom.xml").is_file() || root.join("src/com").is_dir() {
+            Some(Lang::Java)
+        } else if root.join("CMakeLists.txt").is_file() {
+            Some(Lang::Cpp)
+        } else {
+            None
+        }
+    }
+
+    /// where the starterpack for this language keeps the strategy code
+    pub fn default_strategy_path(&self) -> &'static str {
+        match self {
+            Lang::Rust => "src/strategy",
+            Lang::Python => "strategy",
+            Lang::Java => "src/com/bot/strategy",
+            Lang::Cpp => "src/strategy",
+        }
+    }
+
+    /// env vars that stop the language's tooling from reusing stale build state
+    pub fn clean_build_env(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Lang::Rust => &[("CARGO_INCREMENTAL", "0")],
+            Lang::Python => &[("PYTHONDONTWRITEBYTECODE", "1")],
+            // javac has no incremental cache to disable
+            Lang::Java => &[],
+            Lang::Cpp => &[("CCACHE_DISABLE", "1")],
+        }
+    }
 }
 
 impl Display for Lang {
@@ -30,11 +171,13 @@ impl Display for Lang {
             Lang::Rust => write!(f, "rust"),
             Lang::Python => write!(f, "python"),
             Lang::Java => write!(f, "java"),
+            Lang::Cpp => write!(f, "cpp"),
         }
     }
 }
 
-pub fn read(root: &Path) -> anyhow::Result<Config> {
+/// `api_url` is the `--api-url` flag, which beats both `$MM_API_URL` and the file
+pub fn read(root: &Path, api_url: Option<&str>) -> anyhow::Result<Config> {
 
     // println!("reading config file...");
 
@@ -43,11 +186,329 @@ pub fn read(root: &Path) -> anyhow::Result<Config> {
     let content = fs::read_to_string(&file)
         .with_context(|| format!("Failed to read config file: {}", file.display()))?;
 
-    let config: Config = toml::from_str(&content)
+    let mut table: toml::Table = toml::from_str(&content)
+        .with_context(|| format!("failed to parse config from {}", file.display()))?;
+
+    if !table.contains_key("language") {
+        let language = Lang::detect(root)
+            .with_context(|| format!("no language set in {} and it couldn't be detected from the project files", file.display()))?;
+        crate::warning::warn(format_args!(
+            "no language in {}, detected {} from the project files\nrun `mm-cli config init --force` to save it",
+            file.display(), language
+        ))?;
+        table.insert("language".to_string(), language.to_string().into());
+    }
+
+    let mut config: Config = table.try_into()
         .with_context(|| format!("failed to parse config from {}", file.display()))?;
 
+    if let Some(api_url) = api_url {
+        config.api_url = api_url.to_string();
+        config.api_url_overridden = true;
+    } else if let Some(api_url) = env::var(API_URL_ENV).ok().filter(|url| !url.is_empty()) {
+        eprintln!("{}", format!("using api url {} from ${}", api_url, API_URL_ENV).yellow());
+        config.api_url = api_url;
+        config.api_url_overridden = true;
+    }
+
+    config.log_file_name(Utc::now(), "self", "self")
+        .with_context(|| format!("invalid log-name in {}", file.display()))?;
+
+    let escapes_root = |path: &&PathBuf| {
+        path.is_absolute() || path.components().any(|c| matches!(c, std::path::Component::ParentDir))
+    };
+    if let Some(path) = config.strategy_path.as_ref().filter(escapes_root) {
+        bail!("strategy-path '{}' in {} must be inside the project root", path.display(), file.display());
+    }
+
+    for (name, value) in &config.headers {
+        reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("invalid header name '{}' in {}", name, file.display()))?;
+        reqwest::header::HeaderValue::from_str(value)
+            .with_context(|| format!("invalid value for header '{}' in {}", name, file.display()))?;
+    }
+
     // println!("language is {}", config.language);
     // println!("url is {}", config.api_url);
 
     Ok(config)
 }
+
+fn prompt(question: &str) -> anyhow::Result<String> {
+    print!("{}: ", question);
+    io::stdout().flush().ok();
+
+    let mut answer = String::new();
+    let read = io::stdin().read_line(&mut answer)
+        .context("failed to read answer")?;
+    if read == 0 {
+        bail!("no answer for '{}', stdin was closed", question);
+    }
+    Ok(answer.trim().to_string())
+}
+
+/// asks for the language and api url and writes a fresh config into `dir`
+pub fn init(dir: &Path, force: bool) -> anyhow::Result<()> {
+    let file = dir.join(crate::CONFIG_NAME);
+    if file.exists() && !force {
+        bail!("{} already exists, pass --force to overwrite it", file.display());
+    }
+
+    let language = loop {
+        let answer = prompt("language (rust/python/java/cpp)")?;
+        match <Lang as clap::ValueEnum>::from_str(&answer, true) {
+            Ok(language) => break language,
+            Err(_) => eprintln!("{}", format!("unknown language '{}'", answer).red()),
+        }
+    };
+
+    let api_url = loop {
+        let answer = prompt("api url")?;
+        match parse_api_url(&answer) {
+            Ok(url) => break url,
+            Err(err) => eprintln!("{}", err.red()),
+        }
+    };
+
+    let config = Config {
+        language,
+        api_url,
+        log_name: None,
+        strategy_path: None,
+        use_keychain: false,
+        max_submission_size: None,
+        gzip_uploads: false,
+        raw_uploads: false,
+        use_gitignore: false,
+        update_check_timeout: None,
+        api_url_overridden: false,
+        headers: BTreeMap::new(),
+    };
+    let content = toml::to_string(&config).context("failed to serialize config")?;
+
+    if crate::dry_run::skip(format_args!("write {}", file.display())) {
+        return Ok(());
+    }
+    fs::write(&file, content)
+        .with_context(|| format!("failed to write {}", file.display()))?;
+
+    println!("{}", format!("wrote {}", file.display()).green());
+    Ok(())
+}
+
+/// opens the config in the user's editor, reopening it until it parses
+pub fn edit(root: &Path) -> anyhow::Result<()> {
+    let file = root.join(crate::CONFIG_NAME);
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad".into() } else { "vi".into() });
+    // allow things like `EDITOR="code --wait"`
+    let mut editor_args = editor.split_whitespace();
+    let program = editor_args.next().context("$EDITOR is empty")?;
+    let editor_args: Vec<&str> = editor_args.collect();
+
+    loop {
+        let status = Command::new(program)
+            .args(&editor_args)
+            .arg(&file)
+            .status()
+            .with_context(|| format!("failed to launch editor '{}'", editor))?;
+
+        if !status.success() {
+            bail!("editor exited with {}", status);
+        }
+
+        let err = match read(root, None) {
+            Ok(_) => break,
+            Err(err) => err,
+        };
+
+        eprintln!("{}", format!("{:#}", err).red());
+        print!("reopen the editor to fix it? [Y/n] ");
+        io::stdout().flush().ok();
+
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)
+            .context("failed to read answer")?;
+        if answer.trim().eq_ignore_ascii_case("n") {
+            bail!("{} is still invalid", file.display());
+        }
+    }
+
+    println!("{}", "config is valid".green());
+
+    Ok(())
+}
+
+/// one resolved setting and where its value came from
+#[derive(Tabled)]
+pub struct Source {
+    pub setting: &'static str,
+    pub value: String,
+    pub source: String,
+}
+
+/// checks for an http(s) url and drops the trailing slash
+pub fn parse_api_url(s: &str) -> Result<String, String> {
+    match reqwest::Url::parse(s) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(s.trim_end_matches('/').to_string()),
+        Ok(url) => Err(format!("api url must use http or https, not {}", url.scheme())),
+        Err(err) => Err(format!("invalid api url '{}': {}", s, err)),
+    }
+}
+
+/// lists every setting the cli resolved for this project and which layer won
+pub fn sources(root: &Path, config: &Config, root_from_flag: bool, api_url_from_flag: bool, team: Option<&str>) -> anyhow::Result<Vec<Source>> {
+    let file = root.join(crate::CONFIG_NAME);
+
+    // the parsed Config can't tell an explicit value from a serde default
+    let content = fs::read_to_string(&file)
+        .with_context(|| format!("Failed to read config file: {}", file.display()))?;
+    let table: toml::Table = toml::from_str(&content)
+        .with_context(|| format!("failed to parse config from {}", file.display()))?;
+    let file_or_default = |key: &str| if table.contains_key(key) {
+        file.display().to_string()
+    } else {
+        "default".to_string()
+    };
+
+    let token_file = crate::request::token_path(config).ok().filter(|file| file.is_file());
+    let token_source = if crate::keychain::load(root, config).is_some() {
+        "OS keychain".to_string()
+    } else if let Some(file) = token_file {
+        file.display().to_string()
+    } else if !config.api_url_overridden && root.join(crate::JWT_NAME).is_file() {
+        format!("{} (legacy)", root.join(crate::JWT_NAME).display())
+    } else {
+        "—".to_string()
+    };
+
+    Ok(vec![
+        Source {
+            setting: "root",
+            value: root.display().to_string(),
+            source: if root_from_flag { "--root".into() } else { "searched upwards from the current directory".into() },
+        },
+        Source {
+            setting: "language",
+            value: config.language.to_string(),
+            source: if table.contains_key("language") {
+                file.display().to_string()
+            } else {
+                "detected from the project files".to_string()
+            },
+        },
+        Source {
+            setting: "api-url",
+            value: config.api_url.clone(),
+            source: if api_url_from_flag {
+                "--api-url".to_string()
+            } else if env::var(API_URL_ENV).is_ok_and(|url| !url.is_empty()) {
+                format!("${}", API_URL_ENV)
+            } else {
+                file.display().to_string()
+            },
+        },
+        Source {
+            setting: "strategy-path",
+            value: config.strategy_path().display().to_string(),
+            source: file_or_default("strategy-path"),
+        },
+        Source {
+            setting: "log-name",
+            value: config.log_name.clone().unwrap_or_else(|| DEFAULT_LOG_NAME.to_string()),
+            source: file_or_default("log-name"),
+        },
+        Source { setting: "use-keychain", value: config.use_keychain.to_string(), source: file_or_default("use-keychain") },
+        Source {
+            setting: "max-submission-size",
+            value: format!("{} bytes", config.max_submission_size()),
+            source: if table.contains_key("max-upload-size") {
+                file.display().to_string()
+            } else {
+                file_or_default("max-submission-size")
+            },
+        },
+        Source { setting: "gzip-uploads", value: config.gzip_uploads.to_string(), source: file_or_default("gzip-uploads") },
+        Source { setting: "raw-uploads", value: config.raw_uploads.to_string(), source: file_or_default("raw-uploads") },
+        Source { setting: "use-gitignore", value: config.use_gitignore.to_string(), source: file_or_default("use-gitignore") },
+        Source {
+            setting: "update-check-timeout",
+            value: format!("{}s", config.update_check_timeout().as_secs()),
+            source: file_or_default("update-check-timeout"),
+        },
+        Source {
+            setting: "headers",
+            value: if config.headers.is_empty() {
+                "—".to_string()
+            } else {
+                config.headers.keys().cloned().collect::<Vec<_>>().join(", ")
+            },
+            source: file_or_default("headers"),
+        },
+        Source {
+            setting: "token",
+            value: if token_source == "—" { "not logged in".into() } else { "(hidden)".into() },
+            source: token_source,
+        },
+        Source {
+            setting: "team",
+            value: team.unwrap_or("—").to_string(),
+            source: if team.is_some() { "--team".into() } else { "—".into() },
+        },
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_with(files: &[&str]) -> Option<Lang> {
+        let root = tempfile::tempdir().unwrap();
+        for file in files {
+            let path = root.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        Lang::detect(root.path())
+    }
+
+    #[test]
+    fn detects_each_starterpack() {
+        assert!(detect_with(&["Cargo.toml"]) == Some(Lang::Rust));
+        assert!(detect_with(&["requirements.txt"]) == Some(Lang::Python));
+        assert!(detect_with(&["pyproject.toml"]) == Some(Lang::Python));
+        assert!(detect_with(&["pom.xml"]) == Some(Lang::Java));
+        assert!(detect_with(&["src/com/bot/Main.java"]) == Some(Lang::Java));
+        assert!(detect_with(&["CMakeLists.txt"]) == Some(Lang::Cpp));
+    }
+
+    #[test]
+    fn detects_nothing_in_an_empty_project() {
+        assert!(detect_with(&[]).is_none());
+    }
+
+    #[test]
+    fn log_names_include_both_bots() {
+        let mut config: Config = toml::from_str("language = \"rust\"\napi-url = \"http://localhost\"\n").unwrap();
+        config.log_name = Some("{bot-a}-vs-{bot-b}-{date}.mmgl".to_string());
+        let now = DateTime::from_timestamp(0, 0).unwrap();
+        assert_eq!(config.log_file_name(now, "self", "version-3").unwrap(), "self-vs-version-3-19700101.mmgl");
+
+        config.log_name = Some("{seed}.mmgl".to_string());
+        assert!(config.log_file_name(now, "self", "self").is_err());
+    }
+
+    #[test]
+    fn cpp_round_trips_through_the_config_file() {
+        let config: Config = toml::from_str("language = \"cpp\"\napi-url = \"http://localhost\"\n").unwrap();
+        assert!(config.language == Lang::Cpp);
+        assert_eq!(config.language.to_string(), "cpp");
+        assert_eq!(config.strategy_path(), Path::new("src/strategy"));
+
+        let saved = toml::to_string(&config).unwrap();
+        assert!(saved.contains("language = \"cpp\""));
+        assert!(toml::from_str::<Config>(&saved).unwrap().language == Lang::Cpp);
+    }
+}
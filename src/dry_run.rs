@@ -0,0 +1,34 @@
+//! global `--dry-run` switch.
+//!
+//! covers every operation that changes something outside the cli itself:
+//! saving or removing the login token, uploading a submission and canceling it
+//! server-side, switching, labeling and deleting versions, writing a version
+//! export, `config init`, compressing old gamelogs with `logs compress`,
+//! `cargo install` for cli updates, adding or repointing the upstream remote and
+//! the restore/stash/rebase/pop sequence of starterpack updates. read-only work
+//! (building the archive, fetching version lists, `git fetch` during update
+//! checks) still runs so the logged actions reflect what a real run would do.
+
+use std::{fmt::Display, sync::OnceLock};
+
+use colored::Colorize;
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+pub fn init(enabled: bool) {
+    let _ = ENABLED.set(enabled);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+/// when dry-running, logs `action` and returns true so the caller skips it
+pub fn skip(action: impl Display) -> bool {
+    let enabled = enabled();
+    if enabled {
+        // deliberately not silenced by -q, this is the output that was asked for
+        crate::output::status!("{} would {}", "[dry-run]".cyan(), action);
+    }
+    enabled
+}
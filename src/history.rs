@@ -0,0 +1,142 @@
+use std::{fs, io::Write, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tabled::Tabled;
+
+use crate::version::display_missing;
+
+const HISTORY_NAME: &str = ".mm-history.jsonl";
+
+/// one line of the local submission log, independent of what the server remembers
+#[derive(Serialize, Deserialize, Tabled)]
+pub struct HistoryEntry {
+    #[tabled(rename = "submission")]
+    pub submission_id: u32,
+    #[tabled(rename = "submitted at")]
+    pub submitted_at: String,
+    pub result: String,
+    #[tabled(display = "display_missing")]
+    pub commit: Option<String>,
+    // entries written by older versions of the cli lack these
+    #[serde(default)]
+    #[tabled(display = "display_missing")]
+    pub language: Option<String>,
+    /// compressed archive size in bytes
+    #[serde(default)]
+    #[tabled(display = "display_size")]
+    pub size: Option<u64>,
+    /// kept here too, in case the server drops the note
+    #[serde(default)]
+    #[tabled(display = "display_missing")]
+    pub message: Option<String>,
+}
+
+fn display_size(size: &Option<u64>) -> String {
+    size.map(crate::submit::human_size).unwrap_or_else(|| "—".to_string())
+}
+
+pub fn record(root: &Path, entry: &HistoryEntry) -> Result<()> {
+    let file = root.join(HISTORY_NAME);
+
+    let mut history = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&file)
+        .with_context(|| format!("failed to open {}", file.display()))?;
+
+    writeln!(history, "{}", serde_json::to_string(entry)?)
+        .with_context(|| format!("failed to write {}", file.display()))?;
+
+    Ok(())
+}
+
+/// replaces the result of an entry recorded as pending, once compilation finishes
+pub fn set_result(root: &Path, submission_id: u32, result: &str) -> Result<()> {
+    let mut entries = read(root)?;
+    let Some(entry) = entries.iter_mut().rev().find(|e| e.submission_id == submission_id) else {
+        return Ok(());
+    };
+    entry.result = result.to_string();
+
+    let mut content = String::new();
+    for entry in &entries {
+        content.push_str(&serde_json::to_string(entry)?);
+        content.push('\n');
+    }
+    let file = root.join(HISTORY_NAME);
+    fs::write(&file, content).with_context(|| format!("failed to write {}", file.display()))
+}
+
+pub fn read(root: &Path) -> Result<Vec<HistoryEntry>> {
+    let file = root.join(HISTORY_NAME);
+    if !file.exists() {
+        return Ok(Vec::new());
+    }
+
+    fs::read_to_string(&file)
+        .with_context(|| format!("failed to read {}", file.display()))?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line)
+            .with_context(|| format!("corrupt entry in {}: {}", file.display(), line)))
+        .collect()
+}
+
+/// short hash of the checked out commit, if the project is a git repo
+pub async fn current_commit(root: &Path) -> Option<String> {
+    let output = tokio::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .current_dir(root)
+        .output()
+        .await
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8(output.stdout).ok().map(|hash| hash.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(submission_id: u32) -> HistoryEntry {
+        HistoryEntry {
+            submission_id,
+            submitted_at: "2025-01-01T00:00:00+00:00".to_string(),
+            result: "pending".to_string(),
+            commit: None,
+            language: Some("python".to_string()),
+            size: Some(1024),
+            message: None,
+        }
+    }
+
+    #[test]
+    fn set_result_updates_only_that_entry() {
+        let root = tempfile::tempdir().unwrap();
+        record(root.path(), &pending(1)).unwrap();
+        record(root.path(), &pending(2)).unwrap();
+
+        set_result(root.path(), 1, "success").unwrap();
+
+        let entries = read(root.path()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].result, "success");
+        assert_eq!(entries[1].result, "pending");
+        assert_eq!(entries[0].size, Some(1024));
+    }
+
+    #[test]
+    fn set_result_ignores_unknown_submissions() {
+        let root = tempfile::tempdir().unwrap();
+        record(root.path(), &pending(1)).unwrap();
+
+        set_result(root.path(), 9, "failure").unwrap();
+
+        assert_eq!(read(root.path()).unwrap()[0].result, "pending");
+    }
+}
@@ -0,0 +1,58 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::config::Config;
+
+const SERVICE: &str = "mm-cli";
+
+/// one entry per api url, like the token files, so a token is only ever sent
+/// to the server that issued it
+fn entry(config: &Config) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE, &config.api_url)
+}
+
+/// where older versions kept the token, one entry per project root
+fn legacy_entry(root: &Path) -> keyring::Result<keyring::Entry> {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    keyring::Entry::new(SERVICE, &root.to_string_lossy())
+}
+
+pub fn store(config: &Config, token: &str) -> anyhow::Result<()> {
+    entry(config)
+        .and_then(|entry| entry.set_password(token))
+        .context("failed to store token in the OS keychain")
+}
+
+/// `None` when there is no entry or no usable keychain on this machine. the
+/// legacy entry was saved for the config file's server, so it's skipped when
+/// the api url is overridden
+pub fn load(root: &Path, config: &Config) -> Option<String> {
+    entry(config)
+        .and_then(|entry| entry.get_password())
+        .ok()
+        .or_else(|| {
+            if config.api_url_overridden {
+                return None;
+            }
+            legacy_entry(root).and_then(|entry| entry.get_password()).ok()
+        })
+}
+
+/// returns whether an entry was removed
+pub fn delete(root: &Path, config: &Config) -> anyhow::Result<bool> {
+    let mut entries = vec![entry(config)];
+    if !config.api_url_overridden {
+        entries.push(legacy_entry(root));
+    }
+
+    let mut removed = false;
+    for entry in entries {
+        match entry.and_then(|entry| entry.delete_credential()) {
+            Ok(()) => removed = true,
+            Err(keyring::Error::NoEntry) => {}
+            Err(err) => return Err(err).context("failed to remove token from the OS keychain"),
+        }
+    }
+    Ok(removed)
+}
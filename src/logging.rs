@@ -0,0 +1,47 @@
+use clap::ValueEnum;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn directive(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+/// installs the stderr subscriber. `--log-level` wins, then `-v`/`-vv`/`-q`,
+/// then `RUST_LOG`, then `warn`. levels above warn only apply to mm-cli's own
+/// events, dependencies stay at warn unless `RUST_LOG` says otherwise.
+pub fn init(level: Option<LogLevel>, verbose: u8, quiet: bool) {
+    let level = level.or(match (quiet, verbose) {
+        (true, _) => Some(LogLevel::Error),
+        (false, 0) => None,
+        (false, 1) => Some(LogLevel::Info),
+        (false, _) => Some(LogLevel::Debug),
+    });
+    let filter = match level {
+        Some(level @ (LogLevel::Error | LogLevel::Warn)) => EnvFilter::new(level.directive()),
+        Some(level) => EnvFilter::new(format!("warn,mm_cli={}", level.directive())),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+    };
+
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        // follows --no-color / NO_COLOR, which run() applies before this
+        .with_ansi(colored::control::SHOULD_COLORIZE.should_colorize())
+        .try_init();
+}
@@ -1,8 +1,14 @@
 use anyhow::{bail, Context};
+use base64::{Engine as _, engine::general_purpose};
+use colored::Colorize;
 use serde::{Deserialize, Serialize};
-use std::io::{self, Write};
+use std::{io::{self, Write}, path::Path};
 
-use crate::{config::Config, request::parse_response};
+use crate::{config::Config, dry_run, keychain, request::{self, parse_response}};
+
+const LOGIN_ATTEMPTS: u32 = 3;
+/// password for non-interactive logins, e.g. in ci
+const PASSWORD_ENV: &str = "MM_PASSWORD";
 
 #[derive(Serialize)]
 struct LoginRequest {
@@ -15,40 +21,191 @@ struct LoginResponse {
     token: String,
 }
 
-pub async fn login(conf: &Config) -> anyhow::Result<()> {
-    print!("Enter team name: ");
-    io::stdout().flush().unwrap();
+#[derive(Serialize, Deserialize)]
+pub struct WhoamiResponse {
+    pub team_name: String,
+    pub email: Option<String>,
+}
+
+pub async fn login(args: crate::Login, root: &Path, conf: &Config) -> anyhow::Result<()> {
+    let team_name = match args.team.clone() {
+        Some(team_name) => team_name,
+        None => {
+            print!("Enter team name: ");
+            io::stdout().flush().unwrap();
 
-    let mut team_name = String::new();
-    io::stdin().read_line(&mut team_name)
-        .with_context(|| "failed to read team name")?;
+            let mut team_name = String::new();
+            io::stdin().read_line(&mut team_name)
+                .with_context(|| "failed to read team name")?;
+            team_name
+        }
+    };
 
     let team_name = team_name.trim().to_string();
     if team_name.is_empty() {
         bail!("team name cannot be empty");
     }
 
-    let password = rpassword::prompt_password("Enter password: ")
-        .with_context(|| "failed to read password")?;
+    let password = if args.password_stdin {
+        let mut password = String::new();
+        io::stdin().read_line(&mut password)
+            .with_context(|| "failed to read password from stdin")?;
+        password.trim_end_matches(['\r', '\n']).to_string()
+    } else if let Ok(password) = std::env::var(PASSWORD_ENV) {
+        password
+    } else {
+        rpassword::prompt_password("Enter password: ")
+            .with_context(|| "failed to read password")?
+    };
 
     if password.is_empty() {
         bail!("password cannot be empty");
     }
 
-    let response = reqwest::Client::new()
+    let login_request = LoginRequest {
+        username: team_name.clone(),
+        password
+    };
+
+    let client = request::client(conf)?;
+    let req = client
         .post(format!("{}/auth/login", conf.api_url))
-        .json(&LoginRequest {
-            username: team_name.clone(),
-            password
-        })
-        .send()
-        .await?;
+        .json(&login_request);
+    let response = request::send_with_retry(req, LOGIN_ATTEMPTS)
+        .await
+        .with_context(|| format!("couldn't reach the auth server at {}", conf.api_url))?;
+
+    // a 401 here means bad credentials, not a stale saved login
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        bail!("login failed: incorrect team name or password");
+    }
 
     let login_response = parse_response::<LoginResponse>(response).await?;
-    std::fs::write(crate::JWT_NAME, &login_response.token)
-        .context("Failed to save auth token")?;
+    let in_keychain = if args.use_keychain || conf.use_keychain {
+        if dry_run::skip("save the auth token to the OS keychain") {
+            return Ok(());
+        }
+        // e.g. a headless linux box without a secret service running
+        match keychain::store(conf, &login_response.token) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("{}", format!("warning: {:#}, saving it to a file instead", err).yellow());
+                false
+            }
+        }
+    } else {
+        false
+    };
+
+    if !in_keychain {
+        let file = request::token_path(conf)?;
+        if dry_run::skip(format_args!("save the auth token to {}", file.display())) {
+            return Ok(());
+        }
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        std::fs::write(&file, &login_response.token)
+            .context("Failed to save auth token")?;
+        // a keychain entry from an earlier login would shadow the new token
+        let _ = keychain::delete(root, conf);
+    }
     
     println!("login successful for team: {}", team_name);
     
     Ok(())
 }
+
+/// `None` when there is no saved token or the server rejects it
+pub async fn whoami(root: &Path, conf: &Config) -> anyhow::Result<Option<WhoamiResponse>> {
+    match request::read_token(root, conf) {
+        Ok(_) => {}
+        Err(request::AuthError::MissingToken { .. }) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let client = request::client(conf)?;
+    let req = request::authenticate(root, conf, client.get(format!("{}/auth/me", conf.api_url)))?;
+    let response = request::send_with_retry(req, request::SEND_ATTEMPTS)
+        .await
+        .with_context(|| format!("couldn't reach the auth server at {}", conf.api_url))?;
+
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        return Ok(None);
+    }
+
+    parse_response(response).await.map(Some)
+}
+
+/// removes the saved token, being logged out already is not an error
+pub fn logout(root: &Path, conf: &Config) -> anyhow::Result<()> {
+    let mut files = vec![request::token_path(conf)?];
+    if !conf.api_url_overridden {
+        files.push(root.join(crate::JWT_NAME));
+    }
+    if dry_run::skip("remove the saved auth token and any keychain entry for this project") {
+        return Ok(());
+    }
+
+    let mut removed = false;
+    for file in &files {
+        match std::fs::remove_file(file) {
+            Ok(()) => removed = true,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).with_context(|| format!("failed to remove {}", file.display())),
+        }
+    }
+    // machines without a usable keychain never stored anything there
+    removed |= keychain::delete(root, conf).unwrap_or(false);
+
+    if removed {
+        println!("{}", "logged out".green());
+    } else {
+        println!("already logged out, no saved token found");
+    }
+
+    Ok(())
+}
+
+pub fn show_token(root: &Path, conf: &Config) -> anyhow::Result<()> {
+    let token = request::read_token(root, conf)?;
+    let token = token.as_str();
+
+    eprintln!("{}", "warning: this token grants access to your team account, never share it or commit it".yellow().bold());
+    println!("{}", token);
+
+    let claims = match decode_claims(token) {
+        Ok(claims) => claims,
+        Err(err) => {
+            println!("could not decode token payload: {:#}", err);
+            return Ok(());
+        }
+    };
+
+    println!("payload: {}", serde_json::to_string_pretty(&claims)?);
+
+    if let Some(exp) = claims.get("exp").and_then(|exp| exp.as_i64()) {
+        match chrono::DateTime::from_timestamp(exp, 0) {
+            Some(expiry) if expiry < chrono::Utc::now() => println!("expired at {}", expiry),
+            Some(expiry) => println!("expires at {}", expiry),
+            None => println!("invalid expiry: {}", exp),
+        }
+    }
+
+    Ok(())
+}
+
+/// decodes the JWT payload without verifying the signature
+pub fn decode_claims(token: &str) -> anyhow::Result<serde_json::Value> {
+    let payload = token
+        .split('.')
+        .nth(1)
+        .context("token is not a JWT")?;
+
+    let bytes = general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .context("token payload is not valid base64")?;
+
+    serde_json::from_slice(&bytes).context("token payload is not valid JSON")
+}
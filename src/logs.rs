@@ -0,0 +1,59 @@
+use std::{fs, io, path::Path, time::{Duration, SystemTime}};
+
+use anyhow::{Context, Result};
+use flate2::{Compression, write::GzEncoder};
+
+use crate::dry_run;
+
+/// gzips gamelogs older than `args.older_than` days, returns how many were
+/// compressed, or under `--dry-run` how many would have been
+pub fn compress(args: crate::Compress, root: &Path) -> Result<usize> {
+    let logs_path = root.join("logs");
+    if !logs_path.exists() {
+        return Ok(0);
+    }
+
+    // an age too large to represent means no cutoff at all, every log gets compressed
+    let cutoff = args.older_than
+        .checked_mul(24 * 60 * 60)
+        .and_then(|secs| SystemTime::now().checked_sub(Duration::from_secs(secs)));
+    let mut compressed = 0;
+
+    for entry in fs::read_dir(&logs_path)
+        .with_context(|| format!("failed to read {}", logs_path.display()))?
+    {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("mmgl") {
+            continue;
+        }
+
+        let modified = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("failed to read metadata of {}", path.display()))?;
+        if cutoff.is_some_and(|cutoff| modified > cutoff) {
+            continue;
+        }
+
+        let gz_path = path.with_extension("mmgl.gz");
+        if dry_run::skip(format_args!("compress {} to {}", path.display(), gz_path.display())) {
+            compressed += 1;
+            continue;
+        }
+
+        compress_file(&path, &gz_path)
+            .with_context(|| format!("failed to compress {}", path.display()))?;
+        fs::remove_file(&path)
+            .with_context(|| format!("failed to remove {}", path.display()))?;
+        compressed += 1;
+    }
+
+    Ok(compressed)
+}
+
+fn compress_file(from: &Path, to: &Path) -> Result<()> {
+    let mut input = fs::File::open(from)?;
+    let mut encoder = GzEncoder::new(fs::File::create(to)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
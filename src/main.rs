@@ -4,8 +4,19 @@ mod login;
 mod submit;
 mod version;
 mod update;
-
-use std::{env, path::{Path, PathBuf}, process::Stdio};
+mod progress;
+mod dry_run;
+mod logs;
+mod history;
+mod keychain;
+mod warning;
+mod benchmark;
+mod logging;
+mod output;
+#[cfg(test)]
+mod test_server;
+
+use std::{env, io::IsTerminal, path::{Path, PathBuf}, process::Stdio};
 use colored::Colorize;
 
 use anyhow::{bail, Context};
@@ -21,12 +32,58 @@ pub const CONFIG_NAME: &str = "mm-config.toml";
 pub const JWT_NAME: &str = ".mm-token.txt";
 
 
+/// shown by `--version`, `-V` still prints just the crate version
+const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("GIT_SHORT_HASH"),
+    ", built ",
+    env!("BUILD_DATE"),
+    ")"
+);
+
 #[derive(Parser, Clone)]
-#[command(version, about, long_about = None)]
+#[command(version, long_version = LONG_VERSION, about, long_about = None)]
 pub struct Cli {
     /// do not check for updates
     #[arg(long = "ignore-updates")]
     no_updates: bool,
+    /// check for updates even if a check in the last hour found none
+    #[arg(long = "force-update-check", conflicts_with = "no_updates")]
+    force_update_check: bool,
+    /// progress reporting format for long operations
+    #[arg(long = "progress", value_enum, default_value_t = progress::ProgressFormat::Human)]
+    progress: progress::ProgressFormat,
+    /// log uploads, version changes, token and file writes and update steps instead of performing them
+    #[arg(long = "dry-run", global = true)]
+    dry_run: bool,
+    /// path to your mechmania repository (defaults to searching upwards from the current directory)
+    #[arg(long = "root", value_name = "PATH")]
+    root: Option<PathBuf>,
+    /// talk to this backend instead of the one in mm-config.toml or $MM_API_URL
+    #[arg(long = "api-url", value_name = "URL", value_parser = config::parse_api_url)]
+    api_url: Option<String>,
+    /// act on behalf of another team (requires a staff token)
+    #[arg(long = "team", value_name = "NAME")]
+    team: Option<String>,
+    /// fail instead of warning about clock skew, uncommitted or out of date strategy submodules, a detected language or a skipped update check
+    #[arg(long = "strict")]
+    strict: bool,
+    /// print machine-readable json on stdout (version list/show, submit, whoami), other output moves to stderr
+    #[arg(long = "json", global = true)]
+    json: bool,
+    /// disable colored output (also off with $NO_COLOR or when not writing to a terminal)
+    #[arg(long = "no-color", global = true)]
+    no_color: bool,
+    /// log every request's url, status and timing and every ignored file on stderr, -vv adds failed attempts
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+    /// only print results, prompts and errors
+    #[arg(short = 'q', long = "quiet")]
+    quiet: bool,
+    /// diagnostic log verbosity on stderr (defaults to $RUST_LOG, then warn)
+    #[arg(long = "log-level", value_enum)]
+    log_level: Option<logging::LogLevel>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -34,17 +91,49 @@ pub struct Cli {
 #[derive(Subcommand, Clone)]
 pub enum Commands {
     /// log into your team mechmania account
-    Login,
+    Login(Login),
+    /// remove the saved login token
+    Logout,
+    /// show which team the saved login belongs to
+    Whoami,
     /// run your bot against itself
     Run(Run),
     /// direct passthrough to the mm-engine (for more output control)
     Engine(args::ArgConfig),
     /// submit bot for tournaments
-    Submit,
+    Submit(Submit),
     /// switch which bot version you would like to compete
     Version(Version),
     /// update mm-cli and starterpack
-    Update,
+    Update(Update),
+    /// inspect or change mm-config.toml
+    Config(ConfigArgs),
+    /// manage local gamelogs
+    Logs(Logs),
+    /// time repeated builds and matches of your bot
+    Benchmark(Benchmark),
+    /// print a shell completion script
+    ///
+    /// e.g. `source <(mm-cli completions bash)` in ~/.bashrc,
+    /// `mm-cli completions fish > ~/.config/fish/completions/mm-cli.fish`
+    Completions(Completions),
+}
+
+#[derive(Parser, Clone)]
+#[command(about = "log into your team mechmania account")]
+pub struct Login {
+    /// print the stored token and its decoded claims (keep it secret!)
+    #[arg(long = "show-token")]
+    show_token: bool,
+    /// store the token in the OS keychain instead of the user config dir
+    #[arg(long = "use-keychain")]
+    use_keychain: bool,
+    /// team to log in as, instead of prompting for it
+    #[arg(long = "team", value_name = "NAME")]
+    team: Option<String>,
+    /// read the password from the first line of stdin instead of $MM_PASSWORD or a prompt
+    #[arg(long = "password-stdin", requires = "team")]
+    password_stdin: bool,
 }
 
 #[derive(Parser, Clone)]
@@ -53,6 +142,151 @@ pub struct Run {
     /// suppress bot output
     #[arg(short = 'q', long = "quiet")]
     quiet: bool,
+    /// only write the gamelog: no bot output and no build script output
+    #[arg(long = "headless")]
+    headless: bool,
+    /// build without incremental caches (e.g. CARGO_INCREMENTAL=0) for reproducible bots
+    #[arg(long = "clean-build")]
+    clean_build: bool,
+    /// warn if the local clock disagrees with the server (gamelog names use it)
+    #[arg(long = "check-clock")]
+    check_clock: bool,
+    /// abort the match if it runs longer than this many seconds
+    #[arg(long = "timeout", value_name = "SECS")]
+    timeout: Option<u64>,
+    /// play against a past submission (a number or 'latest') downloaded from the server instead of yourself
+    #[arg(long = "version-by", value_name = "N", value_parser = version::parse_version)]
+    version_by: Option<version::Version>,
+    /// play two past submissions against each other instead of your local code
+    #[arg(long = "compare", num_args = 2, value_names = ["A", "B"], value_parser = version::parse_version, conflicts_with_all = ["version_by", "clean_build"])]
+    compare: Option<Vec<version::Version>>,
+    /// play against a random successfully compiled past submission
+    #[arg(long = "opponent-version-random", conflicts_with_all = ["version_by", "compare"])]
+    opponent_version_random: bool,
+    /// write the gamelog here instead of logs/<log-name>
+    #[arg(long = "output", value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// don't write a gamelog at all
+    #[arg(long = "no-log", conflicts_with = "output")]
+    no_log: bool,
+    /// build once, then play this many matches and print a summary
+    #[arg(long = "count", value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    count: u32,
+    /// with --count, keep playing after a failed match and report the failures at the end
+    #[arg(long = "keep-going")]
+    keep_going: bool,
+}
+
+#[derive(Parser, Clone)]
+#[command(about = "time repeated builds and matches of your bot")]
+pub struct Benchmark {
+    /// number of build + match cycles to run
+    #[arg(short = 'n', long = "cycles", default_value_t = 5)]
+    cycles: u32,
+}
+
+#[derive(Parser, Clone)]
+pub struct Update {
+    /// report whether updates are available without installing them
+    #[arg(long = "check", conflicts_with_all = ["cli_only", "starterpack_only", "manual"])]
+    check: bool,
+    /// only update mm-cli itself
+    #[arg(long = "cli-only", conflicts_with = "starterpack_only")]
+    cli_only: bool,
+    /// only pull starterpack changes, without reinstalling mm-cli
+    #[arg(long = "starterpack-only")]
+    starterpack_only: bool,
+    /// leave a conflicted starterpack rebase in progress to resolve by hand instead of aborting it
+    #[arg(long = "manual")]
+    manual: bool,
+}
+
+#[derive(Parser, Clone)]
+pub struct Completions {
+    /// shell to generate completions for
+    #[arg(value_enum)]
+    shell: clap_complete::Shell,
+}
+
+#[derive(Parser, Clone)]
+#[command(about = "submit bot for tournaments")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Submit {
+    #[command(subcommand)]
+    command: Option<SubmitCommands>,
+    /// make the new version active once it compiles
+    #[arg(long = "activate")]
+    activate: bool,
+    /// check the archive unpacks and the bot builds locally before uploading
+    #[arg(long = "verify")]
+    verify: bool,
+    /// print the server's build log as it grows instead of only after a failed compile
+    #[arg(long = "watch-compile")]
+    watch_compile: bool,
+    /// stop polling after this many seconds, the submission keeps compiling on the server
+    #[arg(long = "timeout", value_name = "SECS")]
+    timeout: Option<u64>,
+    /// upload even if the archive is over max-submission-size
+    #[arg(long = "force")]
+    force: bool,
+    /// note shown next to this version in `version list`
+    #[arg(short = 'm', long = "message", value_name = "TEXT")]
+    message: Option<String>,
+    /// submit without asking for confirmation, required when stdin isn't a terminal or with --json
+    #[arg(short = 'y', long = "yes")]
+    yes: bool,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum SubmitCommands {
+    /// show submissions made from this machine
+    History,
+}
+
+#[derive(Parser, Clone)]
+#[command(about = "inspect or change mm-config.toml")]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    command: ConfigCommands
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ConfigCommands {
+    /// create mm-config.toml in the current directory (or --root)
+    Init(Init),
+    /// open the config in $EDITOR and validate it on save
+    Edit,
+    /// show each resolved setting and whether it came from a flag, the config file or a default
+    Sources,
+}
+
+#[derive(Parser, Clone)]
+#[command(about = "")]
+pub struct Init {
+    /// overwrite an existing mm-config.toml
+    #[arg(long = "force")]
+    force: bool,
+}
+
+#[derive(Parser, Clone)]
+#[command(about = "manage local gamelogs")]
+pub struct Logs {
+    #[command(subcommand)]
+    command: LogsCommands
+}
+
+#[derive(Subcommand, Clone)]
+pub enum LogsCommands {
+    /// gzip old gamelogs to save disk space
+    Compress(Compress),
+}
+
+#[derive(Parser, Clone)]
+#[command(about = "")]
+pub struct Compress {
+    /// only compress gamelogs older than this many days
+    #[arg(long = "older-than", value_name = "DAYS", default_value_t = 7)]
+    older_than: u64,
 }
 
 #[derive(Parser, Clone)]
@@ -64,8 +298,27 @@ pub struct Version {
 
 #[derive(Subcommand, Clone)]
 pub enum VersionCommands {
-    List,
-    Switch(Switch)
+    List(List),
+    Switch(Switch),
+    /// print the compile status, error and build log of one version
+    Show(Show),
+    /// set a human readable label on a version
+    Label(Label),
+    /// permanently remove a version that isn't active
+    Delete(Delete),
+    /// write metadata for all versions to a json or csv file
+    Export(Export),
+}
+
+#[derive(Parser, Clone)]
+#[command(about = "")]
+pub struct List {
+    /// only show versions submitted in this language
+    #[arg(long = "lang", value_enum)]
+    lang: Option<Lang>,
+    /// show the server's timestamps instead of "3m ago"
+    #[arg(long = "absolute")]
+    absolute: bool,
 }
 
 
@@ -75,138 +328,502 @@ pub struct Switch {
     /// select version
     #[arg(short = 'v', long = "version", value_parser = version::parse_version)]
     version: Option<version::Version>,
+    /// if the version is still compiling, wait for it instead of failing
+    #[arg(long = "wait-for-compile")]
+    wait_for_compile: bool,
 }
 
-fn strategy_path(config: &config::Config) -> PathBuf {
-    PathBuf::from(match config.language {
-        Lang::Rust => "src/strategy",
-        Lang::Python => "strategy",
-        Lang::Java => "src/com/bot/strategy",
-    })
+#[derive(Parser, Clone)]
+#[command(about = "")]
+pub struct Show {
+    /// version to show (a number or 'latest')
+    #[arg(value_parser = version::parse_version)]
+    version: version::Version,
 }
 
-fn abs_strategy_path(root: &Path, config: &config::Config) -> PathBuf {
-    let strategy_path = match config.language {
-        Lang::Rust => "src/strategy",
-        Lang::Python => "strategy",
-        Lang::Java => "src/com/bot/strategy",
-    };
-    root.join(strategy_path)
+#[derive(Parser, Clone)]
+#[command(about = "")]
+pub struct Label {
+    /// version to label (a number or 'latest')
+    #[arg(value_parser = version::parse_version)]
+    version: version::Version,
+    /// the label, e.g. "aggressive opener"
+    text: String,
 }
 
-async fn run() -> anyhow::Result<()> {
+#[derive(Parser, Clone)]
+#[command(about = "")]
+pub struct Delete {
+    /// version to delete (a number or 'latest')
+    #[arg(value_parser = version::parse_version)]
+    version: version::Version,
+    /// don't ask for confirmation
+    #[arg(short = 'y', long = "yes")]
+    yes: bool,
+}
 
-    // let root = find_project_root()?;
-    let root = find_project_root();
+#[derive(Parser, Clone)]
+#[command(about = "")]
+pub struct Export {
+    /// file to write, the format is picked from the extension unless --format is given
+    path: PathBuf,
+    /// output format
+    #[arg(long = "format", value_enum)]
+    format: Option<version::ExportFormat>,
+}
+
+async fn run() -> anyhow::Result<()> {
 
     let cli = Cli::parse();
-    // let conf = config::read(&root)?;
-    let conf = root
-        .as_ref()
-        .or_else(|_| Err(anyhow::anyhow!("could not read root")))
-        .and_then(|r| config::read(r));
+    let is_terminal = std::io::stdout().is_terminal() && std::io::stderr().is_terminal();
+    if cli.no_color || env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) || !is_terminal {
+        colored::control::set_override(false);
+    }
+    logging::init(cli.log_level, cli.verbose, cli.quiet);
+    progress::init(cli.progress);
+    dry_run::init(cli.dry_run);
+    output::init(cli.json, cli.quiet);
+    warning::init(cli.strict);
+    if let Some(team) = cli.team.clone() {
+        request::set_team(team);
+    }
+
+    // commands only get to see these errors if they actually need root/config
+    let root = match &cli.root {
+        Some(root) => explicit_project_root(root),
+        None => find_project_root(),
+    };
+    let conf = match &root {
+        Ok(root) => config::read(root, cli.api_url.as_deref()),
+        // repeat the root error instead of a vaguer config one
+        Err(err) => Err(anyhow::anyhow!("{:#}", err)),
+    };
 
 
     match cli.command {
-        Commands::Login => login::login(&conf?).await?,
-        Commands::Submit => submit::submit(&root?, &conf?).await?,
+        Commands::Login(args) => if args.show_token {
+            login::show_token(&root?, &conf?)?
+        } else {
+            login::login(args, &root?, &conf?).await?
+        },
+        Commands::Logout => login::logout(&root?, &conf?)?,
+        Commands::Whoami => match login::whoami(&root?, &conf?).await? {
+            me if cli.json => output::print_json(&me)?,
+            Some(me) => {
+                println!("logged in as team {}", me.team_name.bold());
+                if let Some(email) = me.email {
+                    println!("email: {}", email);
+                }
+            },
+            None => println!("not logged in"),
+        },
+        Commands::Submit(Submit { command: Some(SubmitCommands::History), .. }) => {
+            let entries = history::read(&root?)?;
+            if entries.is_empty() {
+                println!("no submissions recorded from this machine yet");
+            } else {
+                println!("{}", tabled::Table::new(&entries));
+            }
+        },
+        Commands::Submit(args) => {
+            let outcome = submit::submit(args, &root?, &conf?).await?;
+            if cli.json {
+                output::print_json(&outcome.as_ref().map(|outcome| serde_json::json!({
+                    "submission_id": outcome.submission_id,
+                    "status": if outcome.success { "success" } else { "failure" },
+                    "error_message": outcome.error_message,
+                    "build_log": outcome.build_log,
+                    "activated_version": outcome.activated.as_ref().map(|switched| switched.version),
+                })))?;
+            } else if let Some(outcome) = outcome {
+                print_submit(&outcome);
+            }
+        },
         Commands::Version(version) => match version.command {
-            VersionCommands::List => version::list(&root?, &conf?).await?,
-            VersionCommands::Switch(v) => version::switch(v, &root?, &conf?).await?,
+            VersionCommands::List(args) => {
+                let filtered = args.lang.is_some();
+                let absolute = args.absolute;
+                let mut versions = version::list(args, &root?, &conf?).await?;
+                if cli.json {
+                    output::print_json(&versions)?;
+                    return Ok(());
+                }
+                if !absolute {
+                    versions.relative_timestamps();
+                }
+                println!("{}", versions);
+                // 'latest' ignores --lang, so only show it for the full table
+                if let Some(latest) = versions.latest().filter(|_| !filtered) {
+                    println!("'latest' resolves to version {}", latest);
+                }
+            },
+            VersionCommands::Switch(v) => {
+                if let Some(outcome) = version::switch(v, &root?, &conf?).await? {
+                    print_switch(&outcome);
+                }
+            },
+            VersionCommands::Show(args) => {
+                let details = version::show(args, &root?, &conf?).await?;
+                if cli.json {
+                    output::print_json(&details)?;
+                    return Ok(());
+                }
+                let info = &details.version;
+                println!("version {} ({}, submitted {})", info.version_number, info.language, version::display_missing(&info.submitted_at));
+                println!("compile status: {}", info.compile_status);
+                if let Some(label) = &info.label {
+                    println!("label: {}", label);
+                }
+                if let Some(error) = &details.error_message {
+                    println!("{}", format!("error: {}", error).red());
+                }
+                match &details.build_log {
+                    Some(log) => println!("build log: \n\n{}", log),
+                    None => println!("no build log available"),
+                }
+            },
+            VersionCommands::Export(args) => {
+                let path = args.path.clone();
+                if let Some(exported) = version::export(args, &root?, &conf?).await? {
+                    println!("exported {} version(s) to {}", exported, path.display());
+                }
+            },
+            VersionCommands::Delete(args) => {
+                if let Some(outcome) = version::delete(args, &root?, &conf?).await? {
+                    println!("Server response: {}", outcome.server_response);
+                    println!("{}", format!("deleted version {}", outcome.version).green());
+                }
+            },
+            VersionCommands::Label(args) => {
+                if let Some(labeled) = version::label(args, &root?, &conf?).await? {
+                    println!("{}", format!("labeled version {}", labeled).green());
+                }
+            },
         },
         Commands::Run(run) => {
 
             let root = root?;
+            let conf = conf?;
 
             if !cli.no_updates {
-                println!("checking for updates...");
-                let needs_update = update::check_all_updates(&root, &conf?)
-                    .await
-                    .context("update check failed")?;
+                output::chatter!("checking for updates...");
+                // being offline shouldn't stop anyone from playing local matches
+                let needs_update = match update::check_all_updates(&root, &conf, cli.force_update_check).await {
+                    Ok(needs_update) => needs_update,
+                    Err(err) => {
+                        warning::warn(format_args!("skipping update check: {:#}", err))?;
+                        false
+                    }
+                };
                 if needs_update {
                     println!("updates needed!\nplease run {}\nif you really wish to run the match, use {}", "mm-cli update".bold(), "--ignore-updates".green());
                     return Ok(());
                 }
             }
 
-            let scripts_path = root.join("scripts");
-            if !scripts_path.exists() {
-                bail!("unable to find build scripts");
+            if run.check_clock {
+                check_clock_skew(&conf).await?;
             }
 
-            let extension = if cfg!(windows) {
-                ".bat"
-            } else {
-                ""
+            use chrono::Utc;
+
+            // kept alive until the matches are over, downloaded bots live in it
+            let bots_dir = tempfile::tempdir().context("failed to create temp dir")?;
+            // a head-to-head always ends with the summary, even for a single match
+            let comparing = run.compare.is_some();
+            // "self" or "version N", for the summary
+            let (player, opponent, names) = match run.compare {
+                // a pure evaluation of past submissions, the local code isn't built
+                Some(versions) => {
+                    let [a, b] = <[version::Version; 2]>::try_from(versions)
+                        .map_err(|_| anyhow::anyhow!("--compare takes exactly two versions"))?;
+                    let (a, path_a) = version::download_artifact(a, &root, &conf, bots_dir.path()).await?;
+                    let (b, path_b) = version::download_artifact(b, &root, &conf, bots_dir.path()).await?;
+                    println!("playing version {} against version {}", a, b);
+                    (path_a, path_b, (format!("version {}", a), format!("version {}", b)))
+                },
+                None => {
+                    let run_path = script_path(&root, "run")?;
+                    let build_env = if run.clean_build { conf.language.clean_build_env() } else { &[] };
+                    build_bot(&root, run.headless, build_env).await?;
+
+                    let (opponent, opponent_name) = match run.version_by {
+                        Some(v) => {
+                            let (number, path) = version::download_artifact(v, &root, &conf, bots_dir.path()).await?;
+                            println!("playing against version {}", number);
+                            (path, format!("version {}", number))
+                        },
+                        None if run.opponent_version_random => {
+                            let v = version::random_compiled(&root, &conf).await?;
+                            let (number, path) = version::download_artifact(v, &root, &conf, bots_dir.path()).await?;
+                            // printed so an interesting match can be replayed with --version-by
+                            println!("playing against randomly chosen version {}", number);
+                            (path, format!("version {}", number))
+                        },
+                        None => (run_path.clone(), "self".to_string()),
+                    };
+                    (run_path, opponent, ("self".to_string(), opponent_name))
+                },
             };
 
-            let build_path = scripts_path.join(format!("build{}", extension));
-            let run_path = scripts_path.join(format!("run{}", extension));
+            let mut failed = 0;
+            for n in 1..=run.count {
+                let log_path = match &run.output {
+                    _ if run.no_log => None,
+                    Some(path) => Some(path.clone()),
+                    None => Some(root.join("logs").join(conf.log_file_name(Utc::now(), &names.0.replace(' ', "-"), &names.1.replace(' ', "-"))?)),
+                };
+                // several matches can start within the same second
+                let log_path = log_path.map(|path| if run.count > 1 { numbered_path(&path, n) } else { path });
+                // the engine doesn't create missing directories for its outputs
+                if let Some(dir) = log_path.as_deref().and_then(Path::parent).filter(|dir| !dir.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(dir)
+                        .with_context(|| format!("failed to create {}", dir.display()))?;
+                }
 
-            if !build_path.exists() {
-                bail!("unable to find build file");
-            }
+                let engine_args = match_args(player.clone(), opponent.clone(), log_path.clone(), !(run.quiet || run.headless));
+
+                if run.count > 1 {
+                    output::chatter!("running match {}/{}...", n, run.count);
+                } else {
+                    output::chatter!("running match...");
+                }
 
-            if !run_path.exists() {
-                bail!("unable to find run file");
+                match play_match(engine_args, run.timeout).await {
+                    Err(err) if !run.keep_going => return Err(err),
+                    Err(err) => {
+                        failed += 1;
+                        eprintln!("{}", format!("match {} failed: {:#}", n, err).red());
+                    },
+                    Ok(()) => match log_path {
+                        Some(log_path) => println!("run successful!, gamelog outputted to {}", log_path.display()),
+                        None => println!("run successful!"),
+                    },
+                }
             }
 
-            println!("building bot...");
+            // the engine reports no result and the gamelog format is its own, so
+            // this counts finished matches rather than wins
+            if run.count > 1 || comparing {
+                println!("{} vs {}: {} of {} matches completed", names.0, names.1, run.count - failed, run.count);
+                if failed > 0 {
+                    bail!("{} of {} matches failed", failed, run.count);
+                }
+            }
+        },
+        Commands::Engine(arg_config) => {
+            check_bot_executable(&arg_config.bot_a).context("bot_a")?;
+            check_bot_executable(&arg_config.bot_b).context("bot_b")?;
 
-            let build_status = tokio::process::Command::new(build_path)
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .spawn()
-                .with_context(|| "failed to spawn build script")?
-                .wait()
+            println!("engine ArgConfig: {:#?}", arg_config);
+            println!("running match...");
+            mm_engine::engine::run(arg_config)
                 .await
-                .with_context(|| "failed to wait for build script")?;
+                .with_context(|| "fatal engine error")?;
+        },
+        Commands::Benchmark(args) => {
+            let stats = benchmark::benchmark(args, &root?).await?;
+            println!("{}", tabled::Table::new(&stats));
+        },
+        Commands::Completions(args) => {
+            use clap::CommandFactory;
+            clap_complete::generate(args.shell, &mut Cli::command(), "mm-cli", &mut std::io::stdout());
+        },
+        Commands::Update(args) => if args.check {
+            update::report(&root?, &conf?).await?
+        } else {
+            let mode = if args.cli_only {
+                update::UpdateMode::CliOnly
+            } else if args.starterpack_only {
+                update::UpdateMode::StarterpackOnly
+            } else {
+                update::UpdateMode::All
+            };
+            update::update_all(mode, args.manual, &root?, &conf?).await?
+        },
+        Commands::Logs(args) => match args.command {
+            LogsCommands::Compress(args) => {
+                let compressed = logs::compress(args, &root?)?;
+                if dry_run::enabled() {
+                    println!("would compress {} gamelog(s)", compressed);
+                } else {
+                    println!("compressed {} gamelog(s)", compressed);
+                }
+            },
+        },
+        Commands::Config(args) => match args.command {
+            ConfigCommands::Init(args) => {
+                let dir = match &cli.root {
+                    Some(dir) => dir.clone(),
+                    None => env::current_dir().context("failed to get current directory")?,
+                };
+                config::init(&dir, args.force)?;
+            },
+            ConfigCommands::Edit => config::edit(&root?)?,
+            ConfigCommands::Sources => {
+                let sources = config::sources(&root?, &conf?, cli.root.is_some(), cli.api_url.is_some(), cli.team.as_deref())?;
+                println!("{}", tabled::Table::new(&sources));
+            },
+        },
+    }
+
+    Ok(())
+}
 
-            if !build_status.success() {
-                bail!("build failed");
+/// gamelog names are local timestamps, so a skewed clock gives misleading log ordering
+async fn check_clock_skew(conf: &config::Config) -> anyhow::Result<()> {
+    const MAX_SKEW_SECS: i64 = 60;
+
+    match request::server_time(conf).await {
+        Ok(server_now) => {
+            let skew = (chrono::Utc::now() - server_now).num_seconds();
+            if skew.abs() > MAX_SKEW_SECS {
+                warning::warn(format_args!(
+                    "your clock is {}s {} the server, gamelog timestamps will be off",
+                    skew.abs(),
+                    if skew > 0 { "ahead of" } else { "behind" }
+                ))?;
             }
+        }
+        Err(err) => warning::warn(format_args!("could not check clock skew: {:#}", err))?,
+    }
 
-            use mm_engine::args::{ OutputSource, OutputMapping };
-            use chrono::Utc;
+    Ok(())
+}
 
+fn print_submit(outcome: &submit::SubmitOutcome) {
+    if !outcome.success {
+        println!("{}", format!("submission {} failed", outcome.submission_id).red());
+        if let Some(reason) = &outcome.error_message {
+            println!("reason: {}", reason);
+        }
+        if !outcome.log_streamed {
+            println!("build log: \n\n{}", outcome.build_log);
+        }
+        println!("for help, please reach out to us on discord");
+        return;
+    }
 
-            let log_path = root.join("logs").join(format!("log-{}.mmgl", Utc::now().format("%Y%m%d_%H%M%S")));
+    println!("{}", format!("submission {} success", outcome.submission_id).green());
 
-            let engine_args = mm_engine::args::ArgConfig {
-                bot_a: run_path.clone(),
-                bot_b: run_path,
-                print: if run.quiet {
-                    None
-                } else {
-                    Some(vec![
-                        OutputSource::BotA,
-                        OutputSource::BotB,
-                    ])
-                },
-                output: Some(vec![
-                    OutputMapping { 
-                        sources: vec![ OutputSource::Gamelog ], 
-                        path: log_path.clone(),
-                    },
-                ]),
-            };
+    if let Some(switched) = &outcome.activated {
+        print_switch(switched);
+    }
+}
 
-            println!("running match...");
+fn print_switch(outcome: &version::SwitchOutcome) {
+    println!("Server response: {}", outcome.server_response);
+    println!("version {} is now active", outcome.version);
+}
 
-            mm_engine::engine::run(engine_args)
-                .await
-                .with_context(|| "fatal engine error")?;
+/// catches the common passthrough mistake before it turns into a deep engine error
+fn check_bot_executable(path: &Path) -> anyhow::Result<()> {
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("{} does not exist", path.display()))?;
 
-            println!("run successful!, gamelog outputted to {}", log_path.display());
-        },
-        Commands::Engine(arg_config) => {
-            println!("engine ArgConfig: {:#?}", arg_config);
-            println!("running match...");
-            mm_engine::engine::run(arg_config)
-                .await
-                .with_context(|| "fatal engine error")?;
+    if !metadata.is_file() {
+        bail!("{} is not a file", path.display());
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            bail!("{} is not executable\nrun `chmod +x {}`", path.display(), path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn script_path(root: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    let scripts_path = root.join("scripts");
+    if !scripts_path.exists() {
+        bail!("unable to find build scripts");
+    }
+
+    let extension = if cfg!(windows) {
+        ".bat"
+    } else {
+        ""
+    };
+
+    let path = scripts_path.join(format!("{}{}", name, extension));
+    if !path.exists() {
+        bail!("unable to find {} file", name);
+    }
+
+    Ok(path)
+}
+
+/// runs one match, tearing it down if it takes longer than `timeout` seconds
+async fn play_match(engine_args: args::ArgConfig, timeout: Option<u64>) -> anyhow::Result<()> {
+    let engine_run = mm_engine::engine::run(engine_args);
+    match timeout {
+        // dropping the engine future on timeout tears the match down
+        Some(secs) => tokio::time::timeout(std::time::Duration::from_secs(secs), engine_run)
+            .await
+            .map_err(|_| anyhow::anyhow!("match timed out after {}s", secs))?
+            .with_context(|| "fatal engine error"),
+        None => engine_run
+            .await
+            .with_context(|| "fatal engine error"),
+    }
+}
+
+/// `logs/game.mmgl` -> `logs/game-3.mmgl`
+fn numbered_path(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// engine args for a local match, writing the gamelog to `log_path` if given
+fn match_args(bot_a: PathBuf, bot_b: PathBuf, log_path: Option<PathBuf>, print_bots: bool) -> args::ArgConfig {
+    use mm_engine::args::{ OutputSource, OutputMapping };
+
+    args::ArgConfig {
+        bot_a,
+        bot_b,
+        print: if print_bots {
+            Some(vec![
+                OutputSource::BotA,
+                OutputSource::BotB,
+            ])
+        } else {
+            None
         },
-        Commands::Update => update::update_all(&root?, &conf?).await?
+        output: log_path.map(|path| vec![
+            OutputMapping { 
+                sources: vec![ OutputSource::Gamelog ], 
+                path,
+            },
+        ]),
+    }
+}
+
+/// runs the starterpack build script, optionally discarding its output
+async fn build_bot(root: &Path, silent: bool, env: &[(&str, &str)]) -> anyhow::Result<()> {
+    let build_path = script_path(root, "build")?;
+
+    output::chatter!("building bot...");
+
+    let build_status = tokio::process::Command::new(build_path)
+        .current_dir(root)
+        .envs(env.iter().copied())
+        .stdout(if silent { Stdio::null() } else { output::child_stdout() })
+        .stderr(if silent { Stdio::null() } else { Stdio::inherit() })
+        .spawn()
+        .with_context(|| "failed to spawn build script")?
+        .wait()
+        .await
+        .with_context(|| "failed to wait for build script")?;
+
+    if !build_status.success() {
+        bail!("build failed");
     }
 
     Ok(())
@@ -221,7 +838,18 @@ fn find_project_root() -> anyhow::Result<PathBuf> {
         }
     }
     
-    anyhow::bail!("could not find {}. make sure you are in your mechmania repository", CONFIG_NAME)
+    anyhow::bail!(
+        "could not find {} in {} or any parent directory\nrun from inside your mechmania repository or pass --root <path>",
+        CONFIG_NAME,
+        current_dir.display()
+    )
+}
+
+fn explicit_project_root(root: &Path) -> anyhow::Result<PathBuf> {
+    if !is_project_root(root) {
+        anyhow::bail!("{} is not a mechmania repository: {} not found", root.display(), CONFIG_NAME);
+    }
+    Ok(root.to_path_buf())
 }
 
 fn is_project_root(dir: &Path) -> bool {
@@ -237,3 +865,28 @@ async fn main() {
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbered_paths_keep_the_extension() {
+        assert_eq!(numbered_path(Path::new("logs/game.mmgl"), 3), Path::new("logs/game-3.mmgl"));
+        assert_eq!(numbered_path(Path::new("game"), 2), Path::new("game-2"));
+    }
+
+    #[test]
+    fn output_overrides_the_gamelog_path() {
+        let args = match_args("run".into(), "run".into(), Some("out/match.mmgl".into()), false);
+        let output = args.output.unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].path, Path::new("out/match.mmgl"));
+    }
+
+    #[test]
+    fn no_log_writes_no_gamelog() {
+        assert!(match_args("run".into(), "run".into(), None, false).output.is_none());
+        assert!(Cli::try_parse_from(["mm-cli", "run", "--no-log", "--output", "x.mmgl"]).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! global `--json` and `-q` switches.
+//!
+//! commands that support `--json` print a single json document on stdout.
+//! everything else they would normally print (status lines, progress dots,
+//! build script output, prompts) goes through [`status!`] and ends up on stderr
+//! instead. `-q` drops the progress chatter in [`chatter!`] but keeps results,
+//! prompts and errors.
+
+use std::{process::Stdio, sync::OnceLock};
+
+static JSON: OnceLock<bool> = OnceLock::new();
+static QUIET: OnceLock<bool> = OnceLock::new();
+
+pub fn init(json: bool, quiet: bool) {
+    let _ = JSON.set(json);
+    let _ = QUIET.set(quiet);
+}
+
+pub fn quiet() -> bool {
+    QUIET.get().copied().unwrap_or(false)
+}
+
+pub fn json() -> bool {
+    JSON.get().copied().unwrap_or(false)
+}
+
+/// where child processes should write what would normally go to stdout
+pub fn child_stdout() -> Stdio {
+    if json() { std::io::stderr().into() } else { Stdio::inherit() }
+}
+
+/// prints the result of a command as pretty json on stdout
+pub fn print_json(value: &impl serde::Serialize) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// `println!` for human status output, moved to stderr under `--json`
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::json() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// `print!` + flush, for prompts and progress dots
+macro_rules! status_inline {
+    ($($arg:tt)*) => {{
+        use std::io::Write as _;
+        if $crate::output::json() {
+            eprint!($($arg)*);
+            std::io::stderr().flush().ok();
+        } else {
+            print!($($arg)*);
+            std::io::stdout().flush().ok();
+        }
+    }};
+}
+
+/// [`status!`] for progress messages, silenced by `-q`
+macro_rules! chatter {
+    ($($arg:tt)*) => {
+        if !$crate::output::quiet() {
+            $crate::output::status!($($arg)*)
+        }
+    };
+}
+
+/// [`status_inline!`] for progress dots, silenced by `-q`
+macro_rules! chatter_inline {
+    ($($arg:tt)*) => {
+        if !$crate::output::quiet() {
+            $crate::output::status_inline!($($arg)*)
+        }
+    };
+}
+
+pub(crate) use {chatter, chatter_inline, status, status_inline};
@@ -0,0 +1,38 @@
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    /// the usual human readable output only
+    #[default]
+    Human,
+    /// additionally emit newline-delimited json events on stderr
+    Json,
+}
+
+static FORMAT: OnceLock<ProgressFormat> = OnceLock::new();
+
+#[derive(Serialize)]
+struct ProgressEvent<'a> {
+    phase: &'a str,
+    percent: Option<u8>,
+    message: &'a str,
+}
+
+pub fn init(format: ProgressFormat) {
+    let _ = FORMAT.set(format);
+}
+
+/// reports a step of a long operation for tools wrapping the cli (`--progress json`)
+pub fn emit(phase: &str, percent: Option<u8>, message: &str) {
+    if FORMAT.get() != Some(&ProgressFormat::Json) {
+        return;
+    }
+
+    let event = ProgressEvent { phase, percent, message };
+    if let Ok(line) = serde_json::to_string(&event) {
+        eprintln!("{}", line);
+    }
+}
@@ -1,8 +1,29 @@
-use std::{fs, path::Path};
+use std::{fs, hash::{DefaultHasher, Hash, Hasher}, path::{Path, PathBuf}, sync::OnceLock, time::{Duration, Instant}};
 use anyhow::Context;
-use reqwest::{RequestBuilder, Response};
-use serde::{de::DeserializeOwned, Deserialize };
+use chrono::{DateTime, Utc};
+use reqwest::{header::{self, HeaderMap, HeaderName, HeaderValue}, Client, RequestBuilder, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize };
 
+use crate::config::Config;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// bumped whenever the cli starts relying on a breaking server api change
+const API_VERSION_HEADER: &str = "x-mm-cli-api";
+const API_VERSION: &str = "1";
+
+/// where older versions cached responses, inside the project
+const LEGACY_CACHE_DIR: &str = ".mm-cache";
+/// cached responses unused for this long are removed
+const CACHE_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// default for `send_with_retry`, matches the submit and login retry loops
+pub const SEND_ATTEMPTS: u32 = 3;
+
+/// lets staff tokens act on behalf of another team
+const TEAM_HEADER: &str = "x-mm-team";
+static TEAM: OnceLock<String> = OnceLock::new();
 
 #[derive(Deserialize)]
 struct ErrorResponse {
@@ -10,36 +31,358 @@ struct ErrorResponse {
     details: Option<String>,
 }
 
-pub fn authenticate(root: &Path, req: RequestBuilder) -> anyhow::Result<RequestBuilder> {
-    let file = root.join(crate::JWT_NAME);
+impl std::fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(details) = &self.details {
+            write!(f, " ({})", details)?;
+        }
+        Ok(())
+    }
+}
+
+/// the http client every command should send requests through
+pub fn client(config: &Config) -> anyhow::Result<Client> {
+    builder(config)?
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .context("failed to build http client")
+}
+
+/// like `client` but without the total timeout, for uploading archives and
+/// downloading bots. those can take far longer than any fixed limit on a slow
+/// link while still making progress. reqwest's read timeout also runs from the
+/// start of the upload, so it's no use here.
+pub fn transfer_client(config: &Config) -> anyhow::Result<Client> {
+    builder(config)?
+        .build()
+        .context("failed to build http client")
+}
+
+fn builder(config: &Config) -> anyhow::Result<reqwest::ClientBuilder> {
+    let mut headers = HeaderMap::new();
+    headers.insert(API_VERSION_HEADER, HeaderValue::from_static(API_VERSION));
+    for (name, value) in &config.headers {
+        // already validated by config::read
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+
+    Ok(Client::builder()
+        .default_headers(headers)
+        .connect_timeout(CONNECT_TIMEOUT))
+}
+
+/// sends `req`, retrying connection failures and timeouts with exponential
+/// backoff. http error statuses are returned as-is, never retried.
+pub async fn send_with_retry(req: RequestBuilder, attempts: u32) -> reqwest::Result<Response> {
+    let mut attempt = 1;
+    loop {
+        // streaming bodies can't be replayed, those only get one try
+        let Some(this_attempt) = req.try_clone() else {
+            return send_logged(req).await;
+        };
+
+        match send_logged(this_attempt).await {
+            Err(err) if attempt < attempts && (err.is_connect() || err.is_timeout()) => {
+                crate::output::chatter!("request failed, retrying ({}/{})...", attempt, attempts - 1);
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// sends `req`, logging its method, url and timing for `-v`
+pub async fn send_logged(req: RequestBuilder) -> reqwest::Result<Response> {
+    let (client, request) = req.build_split();
+    let request = request?;
+    let (method, url) = (request.method().clone(), request.url().clone());
+
+    let started = Instant::now();
+    let result = client.execute(request).await;
+    let elapsed_ms = started.elapsed().as_millis();
+    match &result {
+        Ok(response) => tracing::info!(%method, %url, status = response.status().as_u16(), elapsed_ms, "request finished"),
+        Err(err) => tracing::debug!(%method, %url, elapsed_ms, error = %err, "request failed"),
+    }
+    result
+}
+
+/// reads the server's clock from the `Date` header of a cheap request
+pub async fn server_time(config: &Config) -> anyhow::Result<DateTime<Utc>> {
+    let response = send_logged(client(config)?.head(&config.api_url))
+        .await
+        .with_context(|| format!("failed to reach {}", config.api_url))?;
+
+    let date = response
+        .headers()
+        .get(header::DATE)
+        .context("server did not send a Date header")?
+        .to_str()
+        .context("server sent an invalid Date header")?;
+
+    Ok(DateTime::parse_from_rfc2822(date)
+        .with_context(|| format!("failed to parse server date '{}'", date))?
+        .with_timezone(&Utc))
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+/// sends a GET with `If-None-Match`/`If-Modified-Since` from the last response
+/// and reuses the cached body on 304. entries are keyed by url, credentials and
+/// `--team` so switching teams or servers never serves someone else's data.
+/// entries live in the user cache dir, never in the project.
+pub async fn get_cached<T: DeserializeOwned>(root: &Path, client: &Client, req: RequestBuilder) -> anyhow::Result<T> {
+    // left behind by older versions, where it could end up committed
+    let _ = fs::remove_dir_all(root.join(LEGACY_CACHE_DIR));
+
+    let mut request = req.build().context("failed to build request")?;
+
+    let mut hasher = DefaultHasher::new();
+    request.url().as_str().hash(&mut hasher);
+    for name in [header::AUTHORIZATION.as_str(), TEAM_HEADER] {
+        request.headers().get(name).map(|v| v.as_bytes()).hash(&mut hasher);
+    }
+    // without a cache dir every request just goes out in full
+    let cache_file = cache_dir().map(|dir| dir.join(format!("{:016x}.json", hasher.finish())));
 
-    let content = fs::read_to_string(&file)
-        .with_context(|| format!("failed to read certificate in {}\n\n have you logged in?", file.display()))?;
+    let cached = cache_file
+        .as_ref()
+        .and_then(|file| fs::read_to_string(file).ok())
+        .and_then(|content| serde_json::from_str::<CachedResponse>(&content).ok());
+
+    if let Some(cached) = &cached {
+        let headers = request.headers_mut();
+        if let Some(etag) = cached.etag.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.insert(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(modified) = cached.last_modified.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.insert(header::IF_MODIFIED_SINCE, modified);
+        }
+    }
+
+    let url = request.url().to_string();
+    let response = send_with_retry(RequestBuilder::from_parts(client.clone(), request), SEND_ATTEMPTS)
+        .await
+        .with_context(|| format!("failed to reach {}", url))?;
+
+    if let (StatusCode::NOT_MODIFIED, Some(cached)) = (response.status(), &cached) {
+        tracing::debug!(%url, "not modified, using cached response");
+        return parse_body(&cached.body).context("Failed to parse cached response");
+    }
+
+    if !response.status().is_success() {
+        return parse_response(response).await;
+    }
+
+    let header_string = |name| response
+        .headers()
+        .get(name)
+        .and_then(|v: &HeaderValue| v.to_str().ok())
+        .map(str::to_string);
+    let etag = header_string(header::ETAG);
+    let last_modified = header_string(header::LAST_MODIFIED);
+
+    let body = response.text().await.context("Failed to read response")?;
+    let parsed = parse_body(&body)?;
+
+    if let Some(cache_file) = cache_file.filter(|_| etag.is_some() || last_modified.is_some()) {
+        // the cache is only an optimization, failing to write it is fine
+        let _ = write_cache(&cache_file, &CachedResponse { etag, last_modified, body });
+    }
+
+    Ok(parsed)
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("org", "mechmania", "mm-cli").map(|dirs| dirs.cache_dir().join("responses"))
+}
+
+fn write_cache(file: &Path, entry: &CachedResponse) -> anyhow::Result<()> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+        prune_cache(dir);
+    }
+    fs::write(file, serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// drops entries older than `CACHE_MAX_AGE`, e.g. for tokens that expired long ago
+fn prune_cache(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let stale = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > CACHE_MAX_AGE);
+        if stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// `body` as gzip-compressed json, to be sent with `Content-Encoding: gzip`
+pub fn gzip_json<T: Serialize>(body: &T) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    serde_json::to_writer(&mut encoder, body).context("failed to serialize request body")?;
+    encoder.finish().context("failed to compress request body")
+}
+
+/// every auth failure (no token, empty token, rejected token) ends with this
+const LOGIN_HINT: &str = "please run `mm-cli login`";
+
+/// where `login` saves the token: the user config dir, one file per api url so
+/// logins against different servers don't overwrite each other
+pub fn token_path(config: &Config) -> anyhow::Result<PathBuf> {
+    let dirs = directories::ProjectDirs::from("org", "mechmania", "mm-cli")
+        .context("could not find a config directory for your user")?;
+    let server: String = config
+        .api_url
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    Ok(dirs.config_dir().join("tokens").join(format!("{}.txt", server)))
+}
+
+/// why no token could be read, so callers can tell "log in first" apart from a broken file
+#[derive(Debug)]
+pub enum AuthError {
+    /// no keychain entry and no (or an empty) token file
+    MissingToken { path: PathBuf },
+    /// the token file exists but couldn't be read
+    Unreadable { path: PathBuf, source: std::io::Error },
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::MissingToken { path } => {
+                write!(f, "not logged in: no token found at {}\n\n{}", path.display(), LOGIN_HINT)
+            }
+            AuthError::Unreadable { path, source } => {
+                write!(f, "could not read saved login {}: {}\n\n{}", path.display(), source, LOGIN_HINT)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AuthError::MissingToken { .. } => None,
+            AuthError::Unreadable { source, .. } => Some(source),
+        }
+    }
+}
+
+/// prefers the OS keychain (`login --use-keychain`), then the token in the user
+/// config dir, then the legacy `.mm-token.txt` in the project root
+pub fn read_token(root: &Path, config: &Config) -> Result<String, AuthError> {
+    if let Some(token) = crate::keychain::load(root, config) {
+        return Ok(token);
+    }
+
+    let legacy = root.join(crate::JWT_NAME);
+    let path = match token_path(config) {
+        Ok(file) if file.is_file() || config.api_url_overridden || !legacy.is_file() => file,
+        // the pre-config-dir location, still read for one release
+        _ => legacy,
+    };
+
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(AuthError::MissingToken { path }),
+        Err(source) => return Err(AuthError::Unreadable { path, source }),
+    };
 
     let token = content.trim();
-    Ok(req.bearer_auth(token))
+    if token.is_empty() {
+        return Err(AuthError::MissingToken { path });
+    }
+
+    Ok(token.to_string())
 }
 
-pub async fn parse_response<T: DeserializeOwned>(response: Response) -> anyhow::Result<T> {
+/// checks the saved token's `exp` locally so commands fail up front with a login
+/// hint instead of on a 401 halfway through. the signature is not verified, the
+/// server still has the final say.
+pub fn ensure_authenticated(root: &Path, config: &Config) -> anyhow::Result<()> {
+    let token = read_token(root, config)?;
+    let claims = crate::login::decode_claims(&token)
+        .map_err(|_| anyhow::anyhow!("your saved token looks corrupt, please log in again\n\n{}", LOGIN_HINT))?;
+
+    let expiry = claims
+        .get("exp")
+        .and_then(|exp| exp.as_i64())
+        .and_then(|exp| DateTime::from_timestamp(exp, 0));
+    if let Some(expiry) = expiry.filter(|expiry| *expiry < Utc::now()) {
+        anyhow::bail!("your saved login expired at {}\n\n{}", expiry, LOGIN_HINT);
+    }
+
+    Ok(())
+}
+
+/// scopes every authenticated request to `team` (`--team`, staff only)
+pub fn set_team(team: String) {
+    let _ = TEAM.set(team);
+}
+
+/// the `--team` override, if any
+pub fn team() -> Option<&'static str> {
+    TEAM.get().map(String::as_str)
+}
+
+pub fn authenticate(root: &Path, config: &Config, req: RequestBuilder) -> anyhow::Result<RequestBuilder> {
+    let token = read_token(root, config)?;
+    let req = req.bearer_auth(token);
+    Ok(match TEAM.get() {
+        Some(team) => req.header(TEAM_HEADER, team),
+        None => req,
+    })
+}
+
+/// passes successful responses through and turns everything else into a readable error
+pub async fn check_status(response: Response) -> anyhow::Result<Response> {
     match response.status() {
-        status if status.is_success() => {
-            response
-                .json::<T>()
-                .await
-                .with_context(|| "Failed to parse response")
+        StatusCode::UPGRADE_REQUIRED => {
+            anyhow::bail!(
+                "this version of mm-cli (api v{}) is no longer supported by the server\nplease run mm-cli update",
+                API_VERSION
+            );
+        }
+        status if status.is_success() => Ok(response),
+        StatusCode::UNAUTHORIZED => {
+            let reason = response.json::<ErrorResponse>().await
+                .map(|e| format!(" ({})", e))
+                .unwrap_or_default();
+            anyhow::bail!("your saved login is invalid or expired{}\n\n{}", reason, LOGIN_HINT);
+        }
+        StatusCode::FORBIDDEN => {
+            let reason = response.json::<ErrorResponse>().await
+                .map(|e| format!(": {}", e))
+                .unwrap_or_default();
+            match TEAM.get() {
+                Some(team) => anyhow::bail!("insufficient permissions to act as team {}{}", team, reason),
+                None => anyhow::bail!("insufficient permissions{}", reason),
+            }
         }
         status => {
             if let Ok(error_response) = response.json::<ErrorResponse>().await {
                 match status.as_u16() {
-                    400 => anyhow::bail!("bad request: {}", error_response.error),
-                    401 => anyhow::bail!("authentication failed: {}", error_response.error),
-                    500 => {
-                        let details = error_response.details
-                            .map(|d| format!(" ({})", d))
-                            .unwrap_or_default();
-                        anyhow::bail!("server error: {}{}", error_response.error, details);
-                    }
-                    _ => anyhow::bail!("request failed ({}): {}", status, error_response.error),
+                    400 => anyhow::bail!("bad request: {}", error_response),
+                    500 => anyhow::bail!("server error: {}", error_response),
+                    _ => anyhow::bail!("request failed ({}): {}", status, error_response),
                 }
             } else {
                 anyhow::bail!("request failed with status: {}", status);
@@ -47,3 +390,150 @@ pub async fn parse_response<T: DeserializeOwned>(response: Response) -> anyhow::
         }
     }
 }
+
+/// how much of an unparseable body to show in the error
+const BODY_SNIPPET_LEN: usize = 500;
+
+pub async fn parse_response<T: DeserializeOwned>(response: Response) -> anyhow::Result<T> {
+    let body = check_status(response)
+        .await?
+        .text()
+        .await
+        .context("Failed to read response")?;
+
+    parse_body(&body)
+}
+
+/// parses a json body, quoting the start of it when it doesn't match `T`
+fn parse_body<T: DeserializeOwned>(body: &str) -> anyhow::Result<T> {
+    serde_json::from_str::<T>(body).with_context(|| {
+        let snippet: String = body.chars().take(BODY_SNIPPET_LEN).collect();
+        let more = if snippet.len() < body.len() { "..." } else { "" };
+        format!("Failed to parse response, the server sent:\n{}{}", snippet, more)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Versions {
+        #[allow(dead_code)]
+        versions: Vec<u32>,
+    }
+
+    #[test]
+    fn parse_errors_quote_the_body() {
+        let err = parse_body::<Versions>(r#"{"error": "nope"}"#).unwrap_err();
+        assert!(format!("{:#}", err).contains(r#"{"error": "nope"}"#));
+    }
+
+    #[test]
+    fn long_bodies_are_truncated() {
+        let body = format!("<html>{}</html>", "x".repeat(2 * BODY_SNIPPET_LEN));
+        let message = format!("{:#}", parse_body::<Versions>(&body).unwrap_err());
+        assert!(message.contains("..."));
+        assert!(!message.contains("</html>"));
+    }
+
+    fn response(status: u16, body: &str) -> Response {
+        http::Response::builder()
+            .status(status)
+            .body(body.to_string())
+            .unwrap()
+            .into()
+    }
+
+    #[tokio::test]
+    async fn error_details_are_shown_for_any_status() {
+        let body = r#"{"error": "bad language", "details": "expected one of rust, python"}"#;
+        let err = check_status(response(400, body)).await.unwrap_err();
+        assert_eq!(err.to_string(), "bad request: bad language (expected one of rust, python)");
+
+        let err = check_status(response(404, body)).await.unwrap_err();
+        assert_eq!(err.to_string(), "request failed (404 Not Found): bad language (expected one of rust, python)");
+    }
+
+    #[tokio::test]
+    async fn error_details_are_shown_for_401_and_422() {
+        let body = r#"{"error": "token revoked", "details": "a teammate logged out everywhere"}"#;
+        let message = check_status(response(401, body)).await.unwrap_err().to_string();
+        assert!(message.starts_with("your saved login is invalid or expired (token revoked (a teammate logged out everywhere))"));
+
+        let body = r#"{"error": "invalid archive", "details": "no main.py"}"#;
+        let err = check_status(response(422, body)).await.unwrap_err();
+        assert_eq!(err.to_string(), "request failed (422 Unprocessable Entity): invalid archive (no main.py)");
+    }
+
+    #[tokio::test]
+    async fn errors_without_details_have_no_parentheses() {
+        let err = check_status(response(400, r#"{"error": "bad language"}"#)).await.unwrap_err();
+        assert_eq!(err.to_string(), "bad request: bad language");
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_retried_until_one_succeeds() {
+        use crate::test_server::{serve, Reply};
+
+        let (url, requests) = serve(vec![Reply::Stall, Reply::Stall, Reply::Respond(200, "{}")]).await;
+        let client = Client::builder().timeout(Duration::from_millis(300)).build().unwrap();
+
+        let response = send_with_retry(client.get(format!("{}/bot/versions", url)), SEND_ATTEMPTS).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn error_statuses_are_not_retried() {
+        use crate::test_server::{serve, Reply};
+
+        let (url, requests) = serve(vec![Reply::Respond(404, r#"{"error": "not found"}"#)]).await;
+        let client = Client::new();
+
+        let response = send_with_retry(client.get(format!("{}/bot/versions/9", url)), SEND_ATTEMPTS).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    /// a server no one has a token for, so only the project's files matter
+    fn config(api_url_overridden: bool) -> Config {
+        let mut config: Config = toml::from_str(concat!(
+            "language = \"python\"\n",
+            "api-url = \"http://read-token-test.invalid\"\n",
+        ))
+        .unwrap();
+        config.api_url_overridden = api_url_overridden;
+        config
+    }
+
+    #[test]
+    fn no_token_file_is_missing_token() {
+        let root = tempfile::tempdir().unwrap();
+        let err = read_token(root.path(), &config(false)).unwrap_err();
+        assert!(matches!(err, AuthError::MissingToken { .. }));
+    }
+
+    #[test]
+    fn blank_token_file_is_missing_token() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join(crate::JWT_NAME), " \n").unwrap();
+        let err = read_token(root.path(), &config(false)).unwrap_err();
+        assert!(matches!(err, AuthError::MissingToken { path } if path == root.path().join(crate::JWT_NAME)));
+    }
+
+    #[test]
+    fn legacy_token_is_read_and_trimmed() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join(crate::JWT_NAME), "abc.def.ghi\n").unwrap();
+        assert_eq!(read_token(root.path(), &config(false)).unwrap(), "abc.def.ghi");
+    }
+
+    #[test]
+    fn legacy_token_is_skipped_for_other_servers() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join(crate::JWT_NAME), "abc.def.ghi\n").unwrap();
+        let err = read_token(root.path(), &config(true)).unwrap_err();
+        assert!(matches!(err, AuthError::MissingToken { .. }));
+    }
+}
@@ -1,22 +1,50 @@
-use std::{io::{self, Write}, path::Path};
+use std::{fs, io::{self, IsTerminal, Write}, path::{Path, PathBuf}, time::Duration};
 use crate::{
     config::Config, 
-    request::{authenticate, parse_response}
+    dry_run,
+    history::{self, HistoryEntry},
+    output::{self, chatter, chatter_inline, status, status_inline},
+    progress,
+    request::{self, authenticate, parse_response},
+    version::{SwitchOutcome, Version},
+    warning,
 };
 use colored::Colorize;
 
-use flate2::{Compression, write::GzEncoder};
-use reqwest::Client;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use bytes::Bytes;
+use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
+use reqwest::{Client, header};
 use serde::{Deserialize, Serialize};
-use tar::Builder;
+use tar::{Builder, HeaderMode};
 use anyhow::{bail, Context, Result};
 use base64::{Engine as _, engine::general_purpose};
+use tokio::process::Command;
 
 
+const SUBMIT_ATTEMPTS: u32 = 3;
+const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// small enough for the upload bar to move smoothly on slow links
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+/// gitignore-style patterns, relative to the project root, for files never to submit
+const MMIGNORE_NAME: &str = ".mmignore";
+
 #[derive(Serialize)]
 struct SubmitRequest {
     language: String,
     data: String, 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+}
+
+/// metadata for a `raw-uploads` submission, whose body is just the archive
+#[derive(Serialize)]
+struct RawUploadQuery<'a> {
+    language: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<&'a str>,
 }
 
 #[derive(Deserialize)]
@@ -28,6 +56,10 @@ struct SubmitResponse {
 struct CompilationResponse {
     status: CompilationStatus,
     result: CompilationResult,
+    /// the version this submission became, once compiled. older servers only
+    /// list it with the versions
+    #[serde(default)]
+    version: Option<u32>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -47,8 +79,19 @@ struct CompilationResult {
     build_log: String
 }
 
+pub struct SubmitOutcome {
+    pub submission_id: u32,
+    pub success: bool,
+    pub error_message: Option<String>,
+    pub build_log: String,
+    /// the build log was already printed live by `--watch-compile`
+    pub log_streamed: bool,
+    /// set when `--activate` switched to the new version
+    pub activated: Option<SwitchOutcome>,
+}
+
 
-pub fn compress_folder(folder_path: impl AsRef<Path>) -> Result<Box<[u8]>> {
+pub fn compress_folder(folder_path: impl AsRef<Path>, ignore: &Gitignore) -> Result<Box<[u8]>> {
     let folder_path = folder_path.as_ref();
 
     let buffer = Vec::new();
@@ -56,9 +99,19 @@ pub fn compress_folder(folder_path: impl AsRef<Path>) -> Result<Box<[u8]>> {
     let enc = GzEncoder::new(buffer, Compression::default());
 
     let mut tar = Builder::new(enc);
+    // zero out mtimes and ownership so identical code produces an identical archive
+    tar.mode(HeaderMode::Deterministic);
 
-    tar.append_dir_all("strategy", folder_path)
+    let mut entries = Vec::new();
+    collect_entries(folder_path, Some(ignore), &mut entries)?;
+
+    tar.append_dir("strategy", folder_path)
         .context("failed to compress directory")?;
+    for entry in entries {
+        let name = Path::new("strategy").join(entry.strip_prefix(folder_path)?);
+        tar.append_path_with_name(&entry, &name)
+            .with_context(|| format!("failed to add {} to archive", entry.display()))?;
+    }
 
     tar.finish()
         .context("failed to finalize archive")?;
@@ -72,72 +125,701 @@ pub fn compress_folder(folder_path: impl AsRef<Path>) -> Result<Box<[u8]>> {
     Ok(compressed_data.into_boxed_slice())
 }
 
-pub async fn submit(root: &Path, config: &Config) -> anyhow::Result<()> {
+/// walks `dir` depth-first, pushing every directory and file in sorted order.
+/// ignored directories are skipped along with everything inside them.
+fn collect_entries(dir: &Path, ignore: Option<&Gitignore>, out: &mut Vec<PathBuf>) -> Result<()> {
+    let mut children = fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory {}", dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()
+        .with_context(|| format!("failed to read directory {}", dir.display()))?;
+    children.sort();
 
-    let strategy_path = crate::abs_strategy_path(root, config);
-    if !strategy_path.exists() {
-        bail!("could not find strategy code: {} does not exist", strategy_path.display())
+    for child in children {
+        let is_dir = child.is_dir();
+        if ignore.is_some_and(|ignore| ignore.matched(&child, is_dir).is_ignore()) {
+            tracing::info!(path = %child.display(), "left out of the archive by an ignore rule");
+            continue;
+        }
+        out.push(child.clone());
+        if is_dir {
+            collect_entries(&child, ignore, out)?;
+        }
     }
 
-    let data = compress_folder(strategy_path)?;
-    let encoded_data = general_purpose::STANDARD.encode(&*data);
-    
-    let client = Client::new();
-    
-    println!("submitting bot...");
-    let submit_request = SubmitRequest {
-        language: format!("{}", config.language),
-        data: encoded_data,
-    };
-    
-    let response = authenticate(root, client.post(format!("{}/bot/submit", config.api_url)))?
-        .json(&submit_request)
-        .send()
-        .await
-        .context("failed to submit bot")?;
-    
-    let submit_response: SubmitResponse = parse_response(response).await?;
-    let submission_id = &submit_response.submission_id;
-    
-    println!("{}", "uploaded successfully and queued for submission".green());
+    Ok(())
+}
 
-    let compilation: Option<CompilationResponse>;
+/// the project's `.mmignore` rules, plus its `.gitignore` with `use_gitignore`.
+/// either file may be missing
+fn ignore_rules(root: &Path, use_gitignore: bool) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    let names: &[&str] = if use_gitignore { &[MMIGNORE_NAME, ".gitignore"] } else { &[MMIGNORE_NAME] };
+    for name in names {
+        let file = root.join(name);
+        if !file.is_file() {
+            continue;
+        }
+        if let Some(err) = builder.add(&file) {
+            return Err(err).with_context(|| format!("failed to read {}", file.display()));
+        }
+    }
+    builder.build().context("failed to build ignore rules")
+}
 
-    // poll
-    print!("polling submission status (canceling here will not abort the submission)");
-    io::stdout().flush().unwrap();
+/// with `watch`, prints whatever the server has added to the build log since
+/// the last poll. returns the response and whether any log was streamed, which
+/// stays false on servers that only send the log once compilation finishes.
+async fn poll_compilation(root: &Path, config: &Config, client: &Client, submission_id: u32, watch: bool) -> Result<(CompilationResponse, bool)> {
+    let mut printed = 0;
+    let mut poll = 0;
     loop {
-        print!(".");
-        io::stdout().flush().unwrap();
-        let response = authenticate(root, client.get(&format!("{}/bot/compilation/{}", config.api_url, submission_id)))?
-            .send()
+        if printed == 0 {
+            chatter_inline!(".");
+        }
+        progress::emit("compile", None, "waiting for compilation");
+        let req = authenticate(root, config, client.get(format!("{}/bot/compilation/{}", config.api_url, submission_id)))?;
+        let response = request::send_with_retry(req, request::SEND_ATTEMPTS)
             .await
             .context("failed to check submission status")?;
         
         let status_response: CompilationResponse = parse_response(response).await?;
+
+        if watch {
+            let log = &status_response.result.build_log;
+            // a log that shrank was restarted server-side, show it from the top
+            let new = log.get(printed..).unwrap_or(log);
+            if !new.is_empty() {
+                if printed == 0 {
+                    status!();
+                }
+                status_inline!("{}", new);
+                printed = log.len();
+            }
+        }
         
         if !matches!(status_response.status, CompilationStatus::Pending) {
-            compilation = Some(status_response);
-            break;
+            return Ok((status_response, printed > 0));
         }
 
-        std::thread::sleep(std::time::Duration::from_secs(2));
+        tokio::time::sleep(poll_interval(poll)).await;
+        poll += 1;
+    }
+}
+
+/// 2s, 4s, 8s, ... capped at `MAX_POLL_INTERVAL`
+fn poll_interval(poll: u32) -> Duration {
+    Duration::from_secs(2u64.saturating_pow(poll.saturating_add(1))).min(MAX_POLL_INTERVAL)
+}
+
+/// posts the archive to /bot/submit, falling back from raw and gzip uploads
+/// when the server refuses them and retrying timeouts
+async fn upload(root: &Path, config: &Config, client: &Client, data: &Bytes, note: Option<&str>) -> Result<reqwest::Response> {
+    let language = format!("{}", config.language);
+    // only base64 encoded once a json upload is actually attempted
+    let mut submit_request = None;
+
+    // the same key is resent on every retry so the server can drop duplicates
+    // when an earlier attempt timed out after it was already received
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let mut attempt = 1;
+    let mut gzip = config.gzip_uploads;
+    let mut raw = config.raw_uploads;
+    loop {
+        let req = authenticate(root, config, client.post(format!("{}/bot/submit", config.api_url)))?
+            .header(IDEMPOTENCY_HEADER, &idempotency_key);
+        let (req, body) = if raw {
+            let query = RawUploadQuery { language: &language, note };
+            (req.query(&query).header(header::CONTENT_TYPE, "application/gzip"), data.clone())
+        } else {
+            let submit_request = submit_request.get_or_insert_with(|| SubmitRequest {
+                language: language.clone(),
+                data: general_purpose::STANDARD.encode(&*data),
+                note: note.map(str::to_string),
+            });
+            let req = req.header(header::CONTENT_TYPE, "application/json");
+            if gzip {
+                (req.header(header::CONTENT_ENCODING, "gzip"), request::gzip_json(submit_request)?.into())
+            } else {
+                (req, serde_json::to_vec(submit_request).context("failed to serialize request body")?.into())
+            }
+        };
+        let req = req
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(upload_body(body));
+        let result = request::send_logged(req).await;
+
+        match result {
+            // same for servers that only take the json upload
+            Ok(response) if raw && response.status() == reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE => {
+                chatter!("server does not accept raw uploads, retrying as json...");
+                raw = false;
+            }
+            // servers that can't decode gzip bodies answer 415, resend it plain
+            Ok(response) if gzip && response.status() == reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE => {
+                chatter!("server does not accept gzip uploads, retrying uncompressed...");
+                gzip = false;
+            }
+            Ok(response) => return Ok(response),
+            Err(err) if attempt < SUBMIT_ATTEMPTS && (err.is_connect() || err.is_timeout()) => {
+                chatter!("upload failed, retrying ({}/{})...", attempt, SUBMIT_ATTEMPTS - 1);
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err).context("failed to submit bot"),
+        }
     }
-    println!();
+}
+
+/// ctrl-c while polling: either cancel server-side or just stop waiting,
+/// returns whether the submission was canceled
+async fn interrupted(root: &Path, config: &Config, client: &Client, submission_id: u32) -> Result<bool> {
+    status_inline!("cancel submission {} on the server? [y/N] ", submission_id);
 
-    let compilation = compilation.unwrap();
+    // a plain thread rather than spawn_blocking, so a read left hanging by a
+    // second ctrl-c doesn't keep the runtime from shutting down
+    let (send, answer) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        let mut answer = String::new();
+        let _ = send.send(io::stdin().read_line(&mut answer).map(|_| answer));
+    });
 
-    if !compilation.result.success {
-        println!("{}", "submission failed".red());
-        if let Some(reason) = compilation.result.error_message {
-            println!("reason: {}", reason);
+    // the first ctrl-c's handler is gone by now, listen again so a second one
+    // detaches instead of being swallowed
+    let answer = tokio::select! {
+        answer = answer => answer
+            .context("failed to read answer")?
+            .context("failed to read answer")?,
+        _ = tokio::signal::ctrl_c() => {
+            status!();
+            String::new()
         }
-        println!("build log: \n\n{}", compilation.result.build_log);
-        println!("for help, please reach out to us on discord");
+    };
+
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        status!("stopped waiting, submission {} is still compiling", submission_id);
+        status!("check on it later with {}", "mm-cli version list".bold());
+        return Ok(false);
+    }
+
+    if dry_run::skip(format_args!("cancel submission {}", submission_id)) {
+        return Ok(false);
+    }
+
+    let req = authenticate(root, config, client.post(format!("{}/bot/compilation/{}/cancel", config.api_url, submission_id)))?;
+    let response = request::send_logged(req)
+        .await
+        .context("failed to cancel submission")?;
+    request::check_status(response).await?;
+
+    status!("{}", format!("submission {} canceled", submission_id).yellow());
+    Ok(true)
+}
+
+/// the files `compress_folder` packs, sorted, with their uncompressed sizes
+fn print_listing(strategy_path: &Path, ignore: &Gitignore, data: &[u8]) -> Result<()> {
+    let mut entries = Vec::new();
+    collect_entries(strategy_path, Some(ignore), &mut entries)?;
+
+    for entry in entries.iter().filter(|e| e.is_file()) {
+        let len = fs::metadata(entry)
+            .with_context(|| format!("failed to read {}", entry.display()))?
+            .len();
+        status!("{:>10}  {}", human_size(len), entry.strip_prefix(strategy_path)?.display());
+    }
+    status!("total compressed size: {}", human_size(data.len() as u64));
+
+    Ok(())
+}
+
+pub fn human_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// fails before the upload when the archive is over `max-submission-size`
+/// (skipped with `--force`),
+/// listing the biggest files as candidates to remove
+fn check_size(strategy_path: &Path, ignore: &Gitignore, data: &[u8], max: u64) -> Result<()> {
+    if data.len() as u64 <= max {
         return Ok(());
     }
 
-    println!("{}", "submission success".green());
+    let mut entries = Vec::new();
+    collect_entries(strategy_path, Some(ignore), &mut entries)?;
+    let mut files: Vec<(u64, PathBuf)> = entries
+        .into_iter()
+        .filter_map(|path| Some((fs::metadata(&path).ok().filter(|m| m.is_file())?.len(), path)))
+        .collect();
+    files.sort_by_key(|(len, _)| std::cmp::Reverse(*len));
+
+    let largest = files
+        .iter()
+        .take(5)
+        .map(|(len, path)| format!("  {} ({})", path.strip_prefix(strategy_path).unwrap_or(path).display(), human_size(*len)))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    bail!(
+        "strategy archive is {}, over the max-submission-size of {}\nlargest files (before compression):\n{}\nremove them from {}, add them to {}, or pass --force to upload anyway",
+        human_size(data.len() as u64), human_size(max), largest, strategy_path.display(), MMIGNORE_NAME
+    );
+}
+
+/// unpacks the archive's `strategy/` folder to `dest`, failing if it's corrupt or empty
+fn verify_archive(data: &[u8], dest: &Path) -> Result<()> {
+    let parent = dest.parent().context("strategy path has no parent")?;
+    fs::create_dir_all(parent)
+        .with_context(|| format!("failed to create {}", parent.display()))?;
+    // next to dest so the rename below stays on one filesystem
+    let dir = tempfile::tempdir_in(parent).context("failed to create temp dir")?;
+
+    tar::Archive::new(GzDecoder::new(data))
+        .unpack(dir.path())
+        .context("strategy archive is corrupt")?;
+
+    let mut entries = Vec::new();
+    collect_entries(&dir.path().join("strategy"), None, &mut entries)?;
+    if !entries.iter().any(|e| e.is_file()) {
+        bail!("strategy archive contains no files");
+    }
+
+    fs::rename(dir.path().join("strategy"), dest)
+        .with_context(|| format!("failed to move the unpacked strategy to {}", dest.display()))?;
+
+    chatter!("archive ok ({} entries)", entries.len());
+    Ok(())
+}
+
+/// copies the project for `--verify`, leaving out the strategy folder (replaced by
+/// the unpacked archive), git metadata and build output
+fn copy_project(from: &Path, to: &Path, strategy_path: &Path) -> Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("failed to create {}", to.display()))?;
+
+    for entry in fs::read_dir(from).with_context(|| format!("failed to read directory {}", from.display()))? {
+        let entry = entry.with_context(|| format!("failed to read directory {}", from.display()))?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        if source == strategy_path || matches!(entry.file_name().to_str(), Some(".git" | "target")) {
+            continue;
+        }
+
+        let file_type = entry.file_type().with_context(|| format!("failed to read {}", source.display()))?;
+        if file_type.is_dir() {
+            copy_project(&source, &target, strategy_path)?;
+        } else {
+            // keeps permissions, so the build scripts stay executable
+            fs::copy(&source, &target).with_context(|| format!("failed to copy {}", source.display()))?;
+        }
+    }
 
     Ok(())
 }
+
+/// teams that keep their strategy in a git submodule need it checked out and
+/// committed, otherwise we'd silently upload an empty or stale folder
+async fn check_strategy_submodule(root: &Path, strategy_path: &Path) -> Result<()> {
+    let output = Command::new("git")
+        .args(["submodule", "status"])
+        .current_dir(root)
+        .output()
+        .await;
+
+    // not a git checkout (or no git at all), nothing to check
+    let Ok(output) = output else { return Ok(()) };
+    if !output.status.success() {
+        return Ok(());
+    }
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        // "<state><sha> <path> (<describe>)"
+        let Some(state) = line.chars().next() else { continue };
+        let Some(path) = line[1..].split_whitespace().nth(1) else { continue };
+        let submodule = Path::new(path);
+        if !strategy_path.starts_with(submodule) && !submodule.starts_with(strategy_path) {
+            continue;
+        }
+
+        match state {
+            '-' => bail!(
+                "strategy submodule {} is not initialized\nrun `git submodule update --init {}` first",
+                path, path
+            ),
+            'U' => bail!("strategy submodule {} has merge conflicts", path),
+            '+' => warning::warn(format_args!(
+                "submodule {} is not at the commit recorded by your repo",
+                path
+            ))?,
+            _ => {}
+        }
+
+        let status = Command::new("git")
+            .args(["status", "--porcelain"])
+            .current_dir(root.join(submodule))
+            .output()
+            .await
+            .with_context(|| format!("failed to check status of submodule {}", path))?;
+        if !status.stdout.is_empty() {
+            warning::warn(format_args!(
+                "submodule {} has uncommitted changes, they will be submitted as-is",
+                path
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// `body` split into chunks that share its buffer instead of copying it
+fn upload_chunks(body: Bytes) -> impl Iterator<Item = Bytes> {
+    let total = body.len();
+    (0..total)
+        .step_by(UPLOAD_CHUNK_SIZE)
+        .map(move |start| body.slice(start..total.min(start + UPLOAD_CHUNK_SIZE)))
+}
+
+/// hands `body` to reqwest in chunks so a progress bar can follow the upload
+fn upload_body(body: Bytes) -> reqwest::Body {
+    let total = body.len();
+    let bar = if output::quiet() || output::json() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(total as u64)
+    };
+    // leaves nothing behind once the body is sent, or dropped for a retry
+    let bar = bar.with_finish(ProgressFinish::AndClear);
+    if let Ok(style) = ProgressStyle::with_template("uploading [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec})") {
+        bar.set_style(style.progress_chars("=> "));
+    }
+
+    let chunks = upload_chunks(body).map(move |chunk| {
+        bar.inc(chunk.len() as u64);
+        Ok::<_, io::Error>(chunk)
+    });
+    reqwest::Body::wrap_stream(futures_util::stream::iter(chunks))
+}
+
+/// shows what is about to be uploaded and asks before spending a submission,
+/// returns whether the user agreed
+async fn confirm_submit(root: &Path, config: &Config, strategy_path: &Path, ignore: &Gitignore, data: &[u8]) -> Result<bool> {
+    if output::json() || !io::stdin().is_terminal() {
+        bail!("refusing to submit without confirmation, pass --yes to skip the prompt");
+    }
+
+    let mut entries = Vec::new();
+    collect_entries(strategy_path, Some(ignore), &mut entries)?;
+    let files = entries.iter().filter(|e| e.is_file()).count();
+
+    let team = match request::team() {
+        Some(team) => team.to_string(),
+        // only for the prompt, a failed lookup shouldn't stop the submit
+        None => crate::login::whoami(root, config)
+            .await
+            .ok()
+            .flatten()
+            .map(|me| me.team_name)
+            .unwrap_or_else(|| "your team".to_string()),
+    };
+
+    println!("strategy: {}", strategy_path.display());
+    println!("{} files, {} compressed", files, human_size(data.len() as u64));
+    print!("submit as {}? [y/N] ", team.bold());
+    io::stdout().flush().ok();
+
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read input")?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+/// returns `None` when `--dry-run` skipped the upload, the prompt was declined, or
+/// polling stopped before compilation finished (ctrl-c or `--timeout`)
+pub async fn submit(args: crate::Submit, root: &Path, config: &Config) -> anyhow::Result<Option<SubmitOutcome>> {
+    // a dry run never reaches the server, so it works logged out
+    if !dry_run::enabled() {
+        request::ensure_authenticated(root, config)?;
+    }
+
+    let strategy_path = root.join(config.strategy_path());
+    if !strategy_path.exists() {
+        bail!("could not find strategy code: {} does not exist", strategy_path.display())
+    }
+
+    check_strategy_submodule(root, &config.strategy_path()).await?;
+
+    progress::emit("compress", Some(0), "compressing strategy");
+    let ignore = ignore_rules(root, config.use_gitignore)?;
+    // shared between upload attempts and chunks without copying the archive
+    let data = Bytes::from(compress_folder(&strategy_path, &ignore)?);
+    if !args.force {
+        check_size(&strategy_path, &ignore, &data, config.max_submission_size())?;
+    }
+
+    if args.verify {
+        // build exactly what the server will get, ignored files included in the tree
+        // but not in the archive must not make the build pass
+        let checkout = tempfile::tempdir().context("failed to create temp dir")?;
+        copy_project(root, checkout.path(), &strategy_path)?;
+        verify_archive(&data, &checkout.path().join(config.strategy_path()))?;
+        crate::build_bot(checkout.path(), false, &[]).await
+            .context("local build of the archive failed, fix it before spending a submission")?;
+    }
+    if dry_run::skip(format_args!("upload a {} archive to {}/bot/submit", human_size(data.len() as u64), config.api_url)) {
+        print_listing(&strategy_path, &ignore, &data)?;
+        return Ok(None);
+    }
+
+    if !args.yes && !confirm_submit(root, config, &strategy_path, &ignore, &data).await? {
+        status!("submission cancelled");
+        return Ok(None);
+    }
+    
+    let client = request::client(config)?;
+    let transfer_client = request::transfer_client(config)?;
+    
+    chatter!("submitting bot...");
+    progress::emit("upload", Some(10), "uploading strategy");
+    let response = upload(root, config, &transfer_client, &data, args.message.as_deref()).await?;
+    
+    let submit_response: SubmitResponse = parse_response(response).await?;
+    let submission_id = submit_response.submission_id;
+    
+    chatter!("{}", "uploaded successfully and queued for submission".green());
+    progress::emit("upload", Some(50), "uploaded and queued for compilation");
+
+    // recorded now so a timeout or ctrl-c while polling still leaves a trace
+    let entry = HistoryEntry {
+        submission_id,
+        submitted_at: chrono::Utc::now().to_rfc3339(),
+        result: "pending".to_string(),
+        commit: history::current_commit(root).await,
+        language: Some(config.language.to_string()),
+        size: Some(data.len() as u64),
+        message: args.message.clone(),
+    };
+    if let Err(err) = history::record(root, &entry) {
+        status!("{}", format!("warning: could not record submission history: {:#}", err).yellow());
+    }
+
+    // poll
+    chatter_inline!("polling submission status (press ctrl-c to stop waiting or cancel)");
+    let (compilation, log_streamed) = tokio::select! {
+        compilation = poll_compilation(root, config, &client, submission_id, args.watch_compile) => compilation?,
+        _ = tokio::signal::ctrl_c() => {
+            status!();
+            if interrupted(root, config, &client, submission_id).await? {
+                let _ = history::set_result(root, submission_id, "canceled");
+            }
+            return Ok(None);
+        }
+        _ = async {
+            match args.timeout {
+                Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+                None => std::future::pending().await,
+            }
+        } => {
+            status!();
+            status!("submission {} is still pending after {}s", submission_id, args.timeout.unwrap_or_default());
+            status!("check on it later with {}", "mm-cli version list".bold());
+            return Ok(None);
+        }
+    };
+    status!();
+
+    let result = compilation.result;
+    progress::emit("compile", Some(100), if result.success { "compilation succeeded" } else { "compilation failed" });
+
+    let result_name = if result.success { "success" } else { "failure" };
+    if let Err(err) = history::set_result(root, submission_id, result_name) {
+        status!("{}", format!("warning: could not update submission history: {:#}", err).yellow());
+    }
+
+    let activated = if result.success && args.activate {
+        // not `latest`, a teammate may have submitted since
+        let version = match compilation.version {
+            Some(version) => Some(version),
+            None => crate::version::for_submission(root, config, submission_id).await?,
+        };
+        match version {
+            Some(version) => {
+                chatter!("activating version {}...", version);
+                crate::version::switch(crate::Switch { version: Some(Version::Number(version)), wait_for_compile: false }, root, config).await?
+            }
+            None => {
+                warning::warn(format_args!(
+                    "the server didn't say which version submission {} became, so it was not activated
+switch to it with {}",
+                    submission_id, "mm-cli version switch".bold()
+                ))?;
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    Ok(Some(SubmitOutcome {
+        submission_id,
+        success: result.success,
+        error_message: result.error_message,
+        build_log: result.build_log,
+        log_streamed,
+        activated,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streamed_chunks_match_the_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("small.txt"), "hello").unwrap();
+        // incompressible and bigger than a chunk, so the upload spans several
+        let mut state = 0x2545_f491_u32;
+        let big: Vec<u8> = (0..3 * UPLOAD_CHUNK_SIZE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        fs::write(dir.path().join("big.bin"), &big).unwrap();
+
+        let data = Bytes::from(compress_folder(dir.path(), &Gitignore::empty()).unwrap());
+        let chunks: Vec<Bytes> = upload_chunks(data.clone()).collect();
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= UPLOAD_CHUNK_SIZE));
+        assert_eq!(chunks.concat(), data.to_vec());
+    }
+
+    #[tokio::test]
+    async fn timed_out_uploads_are_retried_with_the_same_key() {
+        use crate::test_server::{serve, Reply};
+
+        let (url, requests) = serve(vec![Reply::Stall, Reply::Respond(200, r#"{"submission_id": 7}"#)]).await;
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join(crate::JWT_NAME), "abc.def.ghi").unwrap();
+        let config: Config = toml::from_str(&format!("language = \"python\"\napi-url = \"{}\"\n", url)).unwrap();
+        let client = Client::builder().timeout(Duration::from_millis(500)).build().unwrap();
+
+        let response = upload(root.path(), &config, &client, &Bytes::from_static(b"archive"), None).await.unwrap();
+        let submitted: SubmitResponse = parse_response(response).await.unwrap();
+        assert_eq!(submitted.submission_id, 7);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|r| r.method == "POST" && r.path == "/bot/submit"));
+        assert_eq!(requests[0].body, requests[1].body);
+        // the server keys submissions on this, so the stalled and the retried
+        // upload make a single submission
+        let keys: std::collections::HashSet<_> = requests.iter().map(|r| r.header(IDEMPOTENCY_HEADER).unwrap()).collect();
+        assert_eq!(keys.len(), 1);
+    }
+
+    #[test]
+    fn custom_strategy_path_is_archived() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(
+            root.path().join(crate::CONFIG_NAME),
+            "language = \"python\"\napi-url = \"http://localhost\"\nstrategy-path = \"bots/mine\"\n",
+        ).unwrap();
+        // the python default, which must not be picked up
+        fs::create_dir_all(root.path().join("strategy")).unwrap();
+        fs::write(root.path().join("strategy/default.py"), "").unwrap();
+        fs::create_dir_all(root.path().join("bots/mine")).unwrap();
+        fs::write(root.path().join("bots/mine/custom.py"), "").unwrap();
+
+        let config = crate::config::read(root.path(), None).unwrap();
+        let data = compress_folder(root.path().join(config.strategy_path()), &ignore_rules(root.path(), config.use_gitignore).unwrap()).unwrap();
+
+        let mut archive = tar::Archive::new(GzDecoder::new(&data[..]));
+        let paths: Vec<PathBuf> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect();
+        assert!(paths.contains(&PathBuf::from("strategy/custom.py")));
+        assert!(!paths.contains(&PathBuf::from("strategy/default.py")));
+    }
+
+    #[test]
+    fn gitignore_only_applies_when_enabled() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join(".gitignore"), "lib/\n").unwrap();
+        let strategy = root.path().join("strategy");
+        fs::create_dir_all(strategy.join("lib")).unwrap();
+        fs::write(strategy.join("lib/helpers.py"), "").unwrap();
+
+        let entries = |use_gitignore| {
+            let mut entries = Vec::new();
+            collect_entries(&strategy, Some(&ignore_rules(root.path(), use_gitignore).unwrap()), &mut entries).unwrap();
+            entries
+        };
+        assert_eq!(entries(false), [strategy.join("lib"), strategy.join("lib/helpers.py")]);
+        assert!(entries(true).is_empty());
+    }
+
+    #[test]
+    fn mmignore_matches_are_skipped() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join(MMIGNORE_NAME), "*.pyc\n__pycache__/\n").unwrap();
+        let strategy = root.path().join("strategy");
+        fs::create_dir_all(strategy.join("__pycache__")).unwrap();
+        fs::write(strategy.join("__pycache__/bot.cpython-312.pyc"), "").unwrap();
+        fs::write(strategy.join("bot.py"), "").unwrap();
+        fs::write(strategy.join("old.pyc"), "").unwrap();
+
+        let mut entries = Vec::new();
+        collect_entries(&strategy, Some(&ignore_rules(root.path(), false).unwrap()), &mut entries).unwrap();
+
+        assert_eq!(entries, [strategy.join("bot.py")]);
+    }
+
+    #[test]
+    fn size_limit_is_inclusive() {
+        let strategy = tempfile::tempdir().unwrap();
+        fs::write(strategy.path().join("weights.bin"), vec![0; 64]).unwrap();
+        let data = [0; 10];
+
+        assert!(check_size(strategy.path(), &Gitignore::empty(), &data, 10).is_ok());
+        let err = check_size(strategy.path(), &Gitignore::empty(), &data, 9).unwrap_err();
+        assert!(err.to_string().contains("weights.bin"));
+    }
+
+    #[test]
+    fn poll_interval_doubles_up_to_the_cap() {
+        let intervals: Vec<u64> = (0..6).map(|poll| poll_interval(poll).as_secs()).collect();
+        assert_eq!(intervals, [2, 4, 8, 16, 30, 30]);
+        assert_eq!(poll_interval(u32::MAX), MAX_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn archive_ignores_creation_order() {
+        let files = [("a.py", "a = 1"), ("sub/b.py", "b = 2"), ("z.py", "z = 3")];
+
+        let forward = tempfile::tempdir().unwrap();
+        let backward = tempfile::tempdir().unwrap();
+        for (dir, files) in [
+            (&forward, files.iter().collect::<Vec<_>>()),
+            (&backward, files.iter().rev().collect()),
+        ] {
+            fs::create_dir(dir.path().join("sub")).unwrap();
+            for (name, content) in files {
+                fs::write(dir.path().join(name), content).unwrap();
+            }
+        }
+
+        assert_eq!(
+            compress_folder(forward.path(), &Gitignore::empty()).unwrap(),
+            compress_folder(backward.path(), &Gitignore::empty()).unwrap(),
+        );
+    }
+}
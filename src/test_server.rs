@@ -0,0 +1,102 @@
+//! a tiny http server for tests. every connection gets the next scripted reply
+//! (the last one repeats) and every request is recorded for the test to inspect.
+
+use std::sync::{Arc, Mutex};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+
+#[derive(Clone, Copy)]
+pub enum Reply {
+    /// read the request, then never answer, so the client times out
+    Stall,
+    Respond(u16, &'static str),
+}
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// header names are lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+pub type Requests = Arc<Mutex<Vec<Request>>>;
+
+/// returns the server's base url and the requests it received so far
+pub async fn serve(replies: Vec<Reply>) -> (String, Requests) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Requests::default();
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        for n in 0.. {
+            let Ok((stream, _)) = listener.accept().await else { return };
+            let reply = replies[n.min(replies.len() - 1)];
+            tokio::spawn(handle(stream, reply, recorded.clone()));
+        }
+    });
+
+    (url, requests)
+}
+
+async fn handle(mut stream: TcpStream, reply: Reply, requests: Requests) {
+    let Some(request) = read_request(&mut stream).await else { return };
+    requests.lock().unwrap().push(request);
+
+    match reply {
+        Reply::Stall => {
+            // keep the connection open without answering until the test is over
+            std::future::pending::<()>().await;
+        }
+        Reply::Respond(status, body) => {
+            let response = format!(
+                "HTTP/1.1 {} Status\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status, body.len(), body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    }
+}
+
+/// only understands content-length bodies, which is all the cli sends
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        let mut chunk = [0; 4096];
+        let read = stream.read(&mut chunk).await.ok().filter(|&read| read > 0)?;
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split(' ');
+    let (method, path) = (request_line.next()?.to_string(), request_line.next()?.to_string());
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let len = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = buffer.split_off(header_end + 4);
+    while body.len() < len {
+        let mut chunk = [0; 4096];
+        let read = stream.read(&mut chunk).await.ok().filter(|&read| read > 0)?;
+        body.extend_from_slice(&chunk[..read]);
+    }
+
+    Some(Request { method, path, headers, body })
+}
@@ -1,52 +1,222 @@
-use std::{path::Path};
+use std::{path::Path, time::Duration};
 use tokio::process::Command;
 use anyhow::{bail, Context, Result};
-use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use crate::{config::Config, dry_run, output::chatter, progress};
 
 const CLI_REPO_URL: &str = "https://github.com/mechmania/cli";
+/// what build.rs embeds as GIT_HASH when git wasn't available
+const UNKNOWN_CLI_HASH: &str = "unknown";
 
-pub async fn check_all_updates(root: &Path, config: &Config) -> Result<bool> {
-    let (cli_updates, starterpack_updates) = tokio::join!(
-        has_cli_updates(),
-        has_upstream_changes(root, config)
-    );
 
-    // Ok(cli_updates? || starterpack_updates?)
-    Ok(cli_updates?)
+/// remembers the last check that found nothing to update, pending updates are
+/// never cached so they keep showing up until they are installed
+const UPDATE_CACHE_NAME: &str = ".mm-update-cache.json";
+const UPDATE_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct UpdateCache {
+    /// unix seconds
+    checked_at: i64,
+}
+
+fn recently_checked(root: &Path) -> bool {
+    std::fs::read_to_string(root.join(UPDATE_CACHE_NAME))
+        .ok()
+        .and_then(|content| serde_json::from_str::<UpdateCache>(&content).ok())
+        .and_then(|cache| u64::try_from(chrono::Utc::now().timestamp() - cache.checked_at).ok())
+        .is_some_and(|age| age < UPDATE_CACHE_TTL.as_secs())
+}
+
+/// skips the network when the last check within the ttl was clean, unless `force`
+pub async fn check_all_updates(root: &Path, config: &Config, force: bool) -> Result<bool> {
+    if !force && recently_checked(root) {
+        return Ok(false);
+    }
+    require_git().await?;
+
+    let checks = async {
+        let (cli_updates, starterpack_updates) = tokio::join!(
+            has_cli_updates(),
+            has_upstream_changes(root, config)
+        );
+        // still fetched so a broken upstream shows up here, but automatic
+        // starterpack updates are switched off, so it doesn't block a run
+        let _ = starterpack_updates?;
+        cli_updates
+    };
+    let Some(needs_update) = within_timeout(checks, config.update_check_timeout()).await else {
+        // reported like any other failed check, a warning that --strict turns into an error
+        bail!("no answer within {}s", config.update_check_timeout().as_secs());
+    };
+
+    let needs_update = needs_update?;
+    if !needs_update {
+        // if this can't be saved, the next run simply checks again
+        let cache = UpdateCache { checked_at: chrono::Utc::now().timestamp() };
+        if let Ok(content) = serde_json::to_string(&cache) {
+            let _ = std::fs::write(root.join(UPDATE_CACHE_NAME), content);
+        }
+    }
+    Ok(needs_update)
+}
+
+/// `None` when `checks` took longer than `timeout`. the git commands are
+/// kill_on_drop, so giving up on them doesn't leave them running
+async fn within_timeout<T>(checks: impl Future<Output = T>, timeout: Duration) -> Option<T> {
+    tokio::time::timeout(timeout, checks).await.ok()
 }
 
-pub async fn update_all(root: &Path, config: &Config) -> Result<()> {
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum UpdateMode {
+    /// whatever needs it
+    All,
+    CliOnly,
+    StarterpackOnly,
+}
+
+/// `manual` leaves a conflicted starterpack rebase for the user to resolve
+pub async fn update_all(mode: UpdateMode, manual: bool, root: &Path, config: &Config) -> Result<()> {
+    require_git().await?;
 
     let (cli_needs_update, starterpack_needs_update) = tokio::join!(
-        has_cli_updates(),
-        has_upstream_changes(root, config)
+        async {
+            if mode == UpdateMode::StarterpackOnly { Ok(false) } else { has_cli_updates().await }
+        },
+        async {
+            if mode == UpdateMode::CliOnly { Ok(false) } else { has_upstream_changes(root, config).await }
+        }
     );
 
-    // let (cli_needs_update, starterpack_needs_update) = (cli_needs_update?, starterpack_needs_update?);
-    let (cli_needs_update, _starterpack_needs_update) = (cli_needs_update?, starterpack_needs_update?);
+    let (cli_needs_update, starterpack_needs_update) = (cli_needs_update?, starterpack_needs_update?);
 
-    let starterpack_needs_update = false;
+    // automatic starterpack updates are still switched off, only --starterpack-only runs them
+    let starterpack_needs_update = starterpack_needs_update && mode == UpdateMode::StarterpackOnly;
     
     if cli_needs_update {
         update_cli().await?;
     }
     
     if starterpack_needs_update {
-        update_starterpack(root, config).await?;
+        update_starterpack(root, config, manual).await?;
     }
     
     if !cli_needs_update && !starterpack_needs_update {
         println!("Everything is up to date!");
+        progress::emit("update", Some(100), "everything is up to date");
     }
     
     Ok(())
 }
 
+/// prints where the cli and starterpack stand without installing anything
+pub async fn report(root: &Path, config: &Config) -> Result<()> {
+    require_git().await?;
+    let (cli, behind) = tokio::join!(
+        cli_versions(),
+        // fetched straight from the url, a check shouldn't touch the user's remotes
+        commits_behind_upstream(root, config, false)
+    );
+    let (cli, behind) = (cli?, behind?);
+
+    match cli {
+        Some(cli) if cli.outdated => println!("cli: update available ({} -> {})", cli.current, cli.latest),
+        Some(cli) => println!("cli: up to date ({})", cli.current),
+        None => println!("cli: built outside a git checkout, can't tell if it's up to date"),
+    }
+
+    match behind {
+        0 => println!("starterpack: up to date"),
+        1 => println!("starterpack: 1 commit behind upstream"),
+        n => println!("starterpack: {} commits behind upstream", n),
+    }
+    Ok(())
+}
+
+/// run before anything shells out to git, so a missing install isn't reported as a raw os error
+async fn require_git() -> Result<()> {
+    match Command::new("git").arg("--version").output().await {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => bail!("git --version failed: {}", String::from_utf8_lossy(&output.stderr)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            bail!("git is required for updates, install it from https://git-scm.com/downloads")
+        },
+        Err(err) => Err(err).context("Failed to run git"),
+    }
+}
+
 async fn has_cli_updates() -> Result<bool> {
+    Ok(cli_versions().await?.is_some_and(|cli| cli.outdated))
+}
+
+/// release versions when the cli repo has tags, short commit hashes otherwise
+struct CliVersions {
+    current: String,
+    latest: String,
+    outdated: bool,
+}
+
+/// `None` when this binary wasn't built from a git checkout, so there's nothing to compare
+async fn cli_versions() -> Result<Option<CliVersions>> {
     let current_hash = get_current_cli_hash();
+    if current_hash == UNKNOWN_CLI_HASH {
+        return Ok(None);
+    }
+
+    // comparing releases means commits that only touch docs or ci don't count as updates
+    if let Some(latest) = get_latest_cli_tag().await? {
+        let current = parse_semver(env!("CARGO_PKG_VERSION"))
+            .context("Invalid crate version")?;
+        return Ok(Some(CliVersions {
+            current: format_semver(current),
+            latest: format_semver(latest),
+            outdated: latest > current,
+        }));
+    }
+
     let latest_hash = get_remote_cli_hash().await?;
-    
-    Ok(current_hash != latest_hash)
+    let short = |hash: &str| hash.chars().take(7).collect::<String>();
+    Ok(Some(CliVersions {
+        current: short(current_hash),
+        latest: short(&latest_hash),
+        outdated: current_hash != latest_hash,
+    }))
+}
+
+/// accepts "1.2.3" and "v1.2.3", pre-releases are ignored
+fn parse_semver(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.strip_prefix('v').unwrap_or(version).split('.');
+    let version = (
+        parts.next()?.parse().ok()?,
+        parts.next()?.parse().ok()?,
+        parts.next()?.parse().ok()?,
+    );
+    parts.next().is_none().then_some(version)
+}
+
+fn format_semver((major, minor, patch): (u64, u64, u64)) -> String {
+    format!("v{}.{}.{}", major, minor, patch)
+}
+
+async fn get_latest_cli_tag() -> Result<Option<(u64, u64, u64)>> {
+    let output = Command::new("git")
+        .args(["ls-remote", "--tags", CLI_REPO_URL])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to check remote CLI tags")?;
+
+    if !output.status.success() {
+        bail!("Failed to fetch remote tags: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    Ok(String::from_utf8(output.stdout)?
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .filter_map(|tag| tag.strip_prefix("refs/tags/"))
+        // annotated tags show up a second time as "<tag>^{}"
+        .filter_map(|tag| parse_semver(tag.trim_end_matches("^{}")))
+        .max())
 }
 
 fn get_current_cli_hash() -> &'static str {
@@ -56,6 +226,7 @@ fn get_current_cli_hash() -> &'static str {
 async fn get_remote_cli_hash() -> Result<String> {
     let output = Command::new("git")
         .args(["ls-remote", CLI_REPO_URL, "HEAD"])
+        .kill_on_drop(true)
         .output()
         .await
         .context("Failed to check remote CLI version")?;
@@ -74,7 +245,12 @@ async fn get_remote_cli_hash() -> Result<String> {
 }
 
 async fn update_cli() -> Result<()> {
-    println!("Updating CLI...");
+    chatter!("Updating CLI...");
+    progress::emit("update-cli", Some(0), "installing latest cli");
+
+    if dry_run::skip(format_args!("run `cargo install --git {}`", CLI_REPO_URL)) {
+        return Ok(());
+    }
     
     let status = Command::new("cargo")
         .args([
@@ -95,15 +271,27 @@ async fn update_cli() -> Result<()> {
     }
     
     println!("CLI updated successfully");
+    progress::emit("update-cli", Some(100), "cli updated");
     Ok(())
 }
 
 async fn has_upstream_changes(root: &Path, config: &Config) -> Result<bool> {
-    add_upstream_remote(root, config).await?;
+    Ok(commits_behind_upstream(root, config, true).await? > 0)
+}
+
+/// with `configure_remote`, adds or repoints the `upstream` remote first so a
+/// following starterpack update can rebase onto it
+async fn commits_behind_upstream(root: &Path, config: &Config, configure_remote: bool) -> Result<u32> {
+    let remote = if configure_remote {
+        add_upstream_remote(root, config).await?
+    } else {
+        get_starterpack_url(config)
+    };
     
     let output = Command::new("git")
-        .args(["fetch", "upstream", "main"])
+        .args(["fetch", remote, "main"])
         .current_dir(root)
+        .kill_on_drop(true)
         .output()
         .await
         .context("Failed to fetch upstream")?;
@@ -113,7 +301,8 @@ async fn has_upstream_changes(root: &Path, config: &Config) -> Result<bool> {
     }
     
     let output = Command::new("git")
-        .args(["rev-list", "--count", "HEAD..upstream/main"])
+        // FETCH_HEAD also covers fetching straight from the url
+        .args(["rev-list", "--count", "HEAD..FETCH_HEAD"])
         .current_dir(root)
         .output()
         .await
@@ -127,16 +316,40 @@ async fn has_upstream_changes(root: &Path, config: &Config) -> Result<bool> {
         .trim()
         .parse::<u32>()?;
     
-    Ok(count > 0)
+    Ok(count)
 }
 
-async fn update_starterpack(root: &Path, config: &Config) -> Result<()> {
-    println!("Updating starterpack...");
+/// `manual` leaves a conflicted rebase for the user instead of aborting it
+async fn update_starterpack(root: &Path, config: &Config, manual: bool) -> Result<()> {
+    chatter!("Updating starterpack...");
+    progress::emit("update-starterpack", Some(0), "updating starterpack");
     
-    let strategy_path = crate::strategy_path(config);
+    let strategy_path = config.strategy_path();
     let strategy_path_str = strategy_path.to_string_lossy();
 
-    println!("restoring non-strategy files...");
+    if dry_run::skip(format_args!(
+        "run `git restore --source=upstream/main` (excluding {}), `git stash`, `git rebase upstream/main` and `git stash pop`",
+        strategy_path_str
+    )) {
+        return Ok(());
+    }
+
+    let backup = Backup::create(root).await?;
+    chatter!("backed up your work first, {}", backup.recovery_hint());
+
+    if let Err(err) = apply_starterpack_update(root, &strategy_path_str, manual).await {
+        bail!("{:#}\n\nif anything went missing, {}", err, backup.recovery_hint());
+    }
+    backup.remove(root).await;
+
+    println!("Starterpack updated successfully");
+    progress::emit("update-starterpack", Some(100), "starterpack updated");
+    Ok(())
+}
+
+async fn apply_starterpack_update(root: &Path, strategy_path_str: &str, manual: bool) -> Result<()> {
+    chatter!("restoring non-strategy files...");
+    progress::emit("update-starterpack", Some(20), "restoring non-strategy files");
     // restore from upstream, excluding strategy
     let output = Command::new("git")
         .args([
@@ -156,7 +369,8 @@ async fn update_starterpack(root: &Path, config: &Config) -> Result<()> {
         bail!("Git restore failed: {}", String::from_utf8_lossy(&output.stderr));
     }
 
-    println!("stashing uncommitted changes in your code...");
+    chatter!("stashing uncommitted changes in your code...");
+    progress::emit("update-starterpack", Some(40), "stashing uncommitted changes");
     // stash, this will stash strategy changes
     
     let output = Command::new("git")
@@ -171,8 +385,15 @@ async fn update_starterpack(root: &Path, config: &Config) -> Result<()> {
     if !output.status.success() {
         bail!("Git stash failed: {}", String::from_utf8_lossy(&output.stderr));
     }
+    let stashed = !String::from_utf8_lossy(&output.stdout).contains("No local changes to save");
+    let stash_hint = if stashed {
+        "your uncommitted changes are saved in the stash, run `git stash pop` to get them back"
+    } else {
+        "you had no uncommitted changes, so there is nothing to restore"
+    };
 
-    println!("applying upstream changes...");
+    chatter!("applying upstream changes...");
+    progress::emit("update-starterpack", Some(60), "applying upstream changes");
     // rebase
     let output = Command::new("git")
         .args([
@@ -185,10 +406,47 @@ async fn update_starterpack(root: &Path, config: &Config) -> Result<()> {
         .context("Failed to run git rebase")?;
 
     if !output.status.success() {
-        bail!("Git rebase failed: {}", String::from_utf8_lossy(&output.stderr));
+        let conflicts = conflicted_files(root).await.unwrap_or_default();
+        if manual {
+            bail!(
+                "the update conflicted with your commits in:\n  {}\n\n\
+                resolve the conflicts and run `git rebase --continue` (or `git rebase --abort` to give up).\n\
+                {}",
+                conflicts.join("\n  "),
+                stash_hint
+            );
+        }
+
+        // put the branch back the way it was before touching anything else
+        let abort = Command::new("git")
+            .args(["rebase", "--abort"])
+            .current_dir(root)
+            .output()
+            .await
+            .context("Failed to run git rebase --abort")?;
+        if !abort.status.success() {
+            bail!(
+                "Git rebase failed and could not be aborted: {}\nrun `git rebase --abort` yourself, {}",
+                String::from_utf8_lossy(&abort.stderr),
+                stash_hint
+            );
+        }
+
+        if conflicts.is_empty() {
+            bail!("Git rebase failed, the update was rolled back: {}\n{}", String::from_utf8_lossy(&output.stderr), stash_hint);
+        }
+        bail!(
+            "the update conflicted with your commits in:\n  {}\n\n\
+            the update was rolled back and your branch is unchanged.\n\
+            {}\n\
+            rerun with `mm-cli update --manual` to resolve the conflicts yourself",
+            conflicts.join("\n  "),
+            stash_hint
+        );
     }
 
-    println!("restoring your uncommitted changes...");
+    chatter!("restoring your uncommitted changes...");
+    progress::emit("update-starterpack", Some(80), "restoring uncommitted changes");
     // stash pop
     let output = Command::new("git")
         .args([
@@ -201,22 +459,140 @@ async fn update_starterpack(root: &Path, config: &Config) -> Result<()> {
         .context("Failed to run git stash pop")?;
 
     if !output.status.success() {
+        // git keeps the stash entry when a pop conflicts, so nothing is lost yet
+        let conflicts = conflicted_files(root).await?;
+        if !conflicts.is_empty() {
+            bail!(
+                "restoring your changes conflicted with the update in:\n  {}\n\n\
+                your changes are still saved in the stash (see `git stash list`).\n\
+                resolve the conflicts, then run `git stash drop` to discard the saved copy",
+                conflicts.join("\n  ")
+            );
+        }
         bail!("Git stash pop failed: {}", String::from_utf8_lossy(&output.stderr));
     }
     
-    println!("Starterpack updated successfully");
     Ok(())
 }
 
-async fn add_upstream_remote(root: &Path, config: &Config) -> Result<()> {
+const BACKUP_HEAD_REF: &str = "refs/mm-cli/backup-head";
+const BACKUP_CHANGES_REF: &str = "refs/mm-cli/backup-changes";
+
+/// refs pointing at the commit and uncommitted changes from before an update,
+/// so a half finished update can always be undone
+struct Backup {
+    head: String,
+    /// `None` when the working tree was clean
+    changes: Option<String>,
+}
+
+impl Backup {
+    async fn create(root: &Path) -> Result<Backup> {
+        let head = git_stdout(root, &["rev-parse", "HEAD"]).await?;
+        // unlike `git stash`, this leaves the working tree and the stash list alone
+        let changes = Some(git_stdout(root, &["stash", "create"]).await?).filter(|sha| !sha.is_empty());
+
+        git_stdout(root, &["update-ref", BACKUP_HEAD_REF, &head]).await?;
+        match &changes {
+            Some(changes) => git_stdout(root, &["update-ref", BACKUP_CHANGES_REF, changes]).await?,
+            // don't leave a previous failed update's changes looking like this one's
+            None => git_stdout(root, &["update-ref", "-d", BACKUP_CHANGES_REF]).await.unwrap_or_default(),
+        };
+
+        Ok(Backup { head, changes })
+    }
+
+    fn recovery_hint(&self) -> String {
+        match &self.changes {
+            Some(changes) => format!(
+                "restore it with `git reset --hard {} && git stash apply {}`",
+                self.head, changes
+            ),
+            None => format!("restore it with `git reset --hard {}`", self.head),
+        }
+    }
+
+    /// best effort, a leftover ref only costs a little disk space
+    async fn remove(&self, root: &Path) {
+        let _ = git_stdout(root, &["update-ref", "-d", BACKUP_HEAD_REF]).await;
+        if self.changes.is_some() {
+            let _ = git_stdout(root, &["update-ref", "-d", BACKUP_CHANGES_REF]).await;
+        }
+    }
+}
+
+async fn git_stdout(root: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(root)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run git {}", args.join(" ")))?;
+
+    if !output.status.success() {
+        bail!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr));
+    }
+
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+async fn conflicted_files(root: &Path) -> Result<Vec<String>> {
+    let output = Command::new("git")
+        .args(["diff", "--name-only", "--diff-filter=U"])
+        .current_dir(root)
+        .output()
+        .await
+        .context("Failed to list conflicted files")?;
+
+    if !output.status.success() {
+        bail!("Git diff failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    Ok(String::from_utf8(output.stdout)?
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+/// returns what to fetch from: the `upstream` remote, or the starterpack url
+/// itself when `--dry-run` left the user's remotes alone
+async fn add_upstream_remote(root: &Path, config: &Config) -> Result<&'static str> {
     let repo_url = get_starterpack_url(config);
-    
-    Command::new("git")
-        .args(["remote", "add", "upstream", repo_url])
+
+    let existing = Command::new("git")
+        .args(["remote", "get-url", "upstream"])
         .current_dir(root)
-        .output().await?;
-        
-    Ok(())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to read the upstream remote")?;
+
+    let args = if !existing.status.success() {
+        ["remote", "add", "upstream", repo_url]
+    } else if String::from_utf8_lossy(&existing.stdout).trim() != repo_url {
+        // e.g. the language changed, fetching the old starterpack would be wrong
+        ["remote", "set-url", "upstream", repo_url]
+    } else {
+        return Ok("upstream");
+    };
+
+    if dry_run::skip(format_args!("run `git {}`", args.join(" "))) {
+        return Ok(repo_url);
+    }
+
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(root)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to configure the upstream remote")?;
+
+    if !output.status.success() {
+        bail!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr));
+    }
+
+    Ok("upstream")
 }
 
 fn get_starterpack_url(config: &Config) -> &'static str {
@@ -225,5 +601,55 @@ fn get_starterpack_url(config: &Config) -> &'static str {
         Lang::Rust => "https://github.com/mechmania/rust-starterpack",
         Lang::Python => "https://github.com/mechmania/python-starterpack",
         Lang::Java => "https://github.com/mechmania/java-starterpack",
+        Lang::Cpp => "https://github.com/mechmania/cpp-starterpack",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn slow_checks_time_out() {
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            true
+        };
+        assert_eq!(within_timeout(slow, Duration::from_millis(10)).await, None);
+    }
+
+    #[tokio::test]
+    async fn fast_checks_finish() {
+        assert_eq!(within_timeout(async { true }, Duration::from_secs(5)).await, Some(true));
+    }
+
+    #[test]
+    fn semver_tags_parse_with_or_without_v() {
+        assert_eq!(parse_semver("v1.2.3"), Some((1, 2, 3)));
+        assert_eq!(parse_semver("0.10.0"), Some((0, 10, 0)));
+        assert_eq!(parse_semver("v1.2"), None);
+        assert_eq!(parse_semver("v1.2.3.4"), None);
+        assert_eq!(parse_semver("v1.2.3-rc.1"), None);
+    }
+
+    async fn git(root: &Path, args: &[&str]) -> String {
+        let output = Command::new("git").args(args).current_dir(root).output().await.unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn upstream_remote_is_added_then_repointed() {
+        let root = tempfile::tempdir().unwrap();
+        git(root.path(), &["init", "-q"]).await;
+        let mut config: Config = toml::from_str("language = \"rust\"\napi-url = \"http://localhost\"\n").unwrap();
+
+        assert_eq!(add_upstream_remote(root.path(), &config).await.unwrap(), "upstream");
+        assert_eq!(git(root.path(), &["remote", "get-url", "upstream"]).await, get_starterpack_url(&config));
+
+        // switching languages must not keep fetching the old starterpack
+        config.language = crate::config::Lang::Python;
+        assert_eq!(add_upstream_remote(root.path(), &config).await.unwrap(), "upstream");
+        assert_eq!(git(root.path(), &["remote", "get-url", "upstream"]).await, get_starterpack_url(&config));
     }
 }
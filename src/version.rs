@@ -1,11 +1,15 @@
-use std::path::Path;
-use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::io::{self, IsTerminal, Write};
 use crate::{
     config::Config, 
-    request::{authenticate, parse_response}
+    dry_run,
+    output::{chatter_inline, status, status_inline},
+    progress,
+    request::{self, authenticate}
 };
 use anyhow::Context;
-use reqwest::Client;
+use futures_util::StreamExt;
+use colored::Colorize;
 use serde::{Deserialize, Serialize};
 use tabled::Tabled;
 
@@ -26,8 +30,12 @@ pub fn parse_version(s: &str) -> Result<Version, String> {
 }
 
 
-#[derive(Deserialize)]
-enum CompileStatus {
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompileStatus {
+    /// also used when the server omits the status entirely
+    #[default]
+    #[serde(rename = "pending")]
+    Pending,
     #[serde(rename = "success")]
     Success,
     #[serde(rename = "failure")]
@@ -37,26 +45,91 @@ enum CompileStatus {
 impl std::fmt::Display for CompileStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", match self {
+            CompileStatus::Pending => "pending",
             CompileStatus::Success => "success",
             CompileStatus::Failure => "failure",
         })
     }
 }
 
-#[derive(Deserialize, Tabled)]
-struct VersionResponse {
+#[derive(Serialize, Deserialize, Tabled)]
+pub struct VersionResponse {
     #[serde(rename = "version")]
-    version_number: u32,
-    language: String,
-    compile_status: CompileStatus,
-    compiled_at: String,
-    submitted_at: String,
+    pub version_number: u32,
+    pub language: String,
+    // pending or partially recorded versions can be missing any of these,
+    // which shouldn't take down the whole list
+    #[serde(default)]
+    pub compile_status: CompileStatus,
+    #[tabled(display = "display_missing")]
+    pub compiled_at: Option<String>,
+    #[tabled(display = "display_missing")]
+    pub submitted_at: Option<String>,
+    #[tabled(display = "display_missing")]
+    pub label: Option<String>,
+    /// from `submit --message`, older servers don't send it
+    #[serde(default)]
+    #[tabled(display = "display_missing")]
+    pub note: Option<String>,
+    /// the submission that created this version, older servers don't send it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[tabled(skip)]
+    pub submission_id: Option<u32>,
 }
 
-#[derive(Deserialize)]
-struct VersionsResponse {
-    versions: Vec<VersionResponse>,
-    active_version: Option<u32>,
+pub fn display_missing(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "—".to_string())
+}
+
+/// a single version including why it failed to compile
+#[derive(Serialize, Deserialize)]
+pub struct VersionDetails {
+    #[serde(flatten)]
+    pub version: VersionResponse,
+    pub error_message: Option<String>,
+    pub build_log: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VersionsResponse {
+    pub versions: Vec<VersionResponse>,
+    pub active_version: Option<u32>,
+}
+
+impl VersionsResponse {
+    /// the version number that `latest` resolves to
+    pub fn latest(&self) -> Option<u32> {
+        self.versions.iter().map(|v| v.version_number).max()
+    }
+}
+
+/// renders an rfc3339 timestamp as e.g. "3m ago", keeping the raw string if it doesn't parse
+fn relative_time(raw: &str, now: chrono::DateTime<chrono::Utc>) -> String {
+    let Ok(time) = chrono::DateTime::parse_from_rfc3339(raw) else {
+        return raw.to_string();
+    };
+
+    let secs = (now - time.with_timezone(&chrono::Utc)).num_seconds();
+    match secs {
+        // slightly ahead of us means our clock is behind the server's
+        ..=0 => "just now".to_string(),
+        1..60 => format!("{}s ago", secs),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+impl VersionsResponse {
+    /// swaps the server timestamps for relative ones, only meant for the table
+    pub fn relative_timestamps(&mut self) {
+        let now = chrono::Utc::now();
+        for version in &mut self.versions {
+            for time in [&mut version.compiled_at, &mut version.submitted_at].into_iter().flatten() {
+                *time = relative_time(time, now);
+            }
+        }
+    }
 }
 
 impl std::fmt::Display for VersionsResponse {
@@ -69,37 +142,104 @@ impl std::fmt::Display for VersionsResponse {
     }
 }
 
+const COMPILE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
 #[derive(Serialize)]
 struct SwitchRequest {
     version: u32
 }
 
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Serialize)]
+struct LabelRequest {
+    label: String,
+}
+
+pub struct SwitchOutcome {
+    pub version: u32,
+    pub server_response: String,
+}
+
 async fn get_versions(root: &Path, config: &Config) -> anyhow::Result<VersionsResponse> {
-    let client = Client::new();
+    let client = request::client(config)?;
     // fetch current versions
-    let versions = authenticate(root, client.get(format!("{}/bot/versions", config.api_url)))?
-        .send()
+    let req = authenticate(root, config, client.get(format!("{}/bot/versions", config.api_url)))?;
+    request::get_cached::<VersionsResponse>(root, &client, req)
         .await
-        .context("failed to fetch bot versions")?;
-    Ok(parse_response::<VersionsResponse>(versions).await?)
+        .context("failed to fetch bot versions")
 }
 
 
-pub async fn list(root: &Path, config: &Config) -> anyhow::Result<()> {
-    let versions = get_versions(root, config).await?;
+pub async fn list(args: crate::List, root: &Path, config: &Config) -> anyhow::Result<VersionsResponse> {
+    request::ensure_authenticated(root, config)?;
+    let mut versions = get_versions(root, config).await?;
 
-    // Print table
-    println!("{}", versions);
+    if let Some(lang) = args.lang {
+        let lang = lang.to_string();
+        versions.versions.retain(|vr| vr.language == lang);
+    }
+
+    Ok(versions)
+}
 
-    // Show what "latest" means
-    if let Some(max_version) = versions.versions.iter().map(|v| v.version_number).max() {
-        println!("'latest' resolves to version {}", max_version);
+/// arrow-key selector over all versions, with failed compiles greyed out
+fn pick_version(versions: &VersionsResponse) -> anyhow::Result<u32> {
+    if versions.versions.is_empty() {
+        anyhow::bail!("No versions available to switch to");
     }
 
-    Ok(())
+    let items: Vec<String> = versions
+        .versions
+        .iter()
+        .map(|vr| {
+            let mut row = format!(
+                "version {} ({}, {}, submitted {})",
+                vr.version_number, vr.language, vr.compile_status, display_missing(&vr.submitted_at)
+            );
+            if versions.active_version == Some(vr.version_number) {
+                row.push_str(" [active]");
+            }
+            match vr.compile_status {
+                CompileStatus::Success => row,
+                CompileStatus::Pending | CompileStatus::Failure => row.dimmed().to_string(),
+            }
+        })
+        .collect();
+
+    let default = versions
+        .versions
+        .iter()
+        .position(|vr| versions.active_version == Some(vr.version_number))
+        .unwrap_or(0);
+
+    let selection = dialoguer::Select::new()
+        .with_prompt("Select version to switch to")
+        .items(&items)
+        .default(default)
+        .interact_opt()
+        .context("Failed to read selection")?
+        .context("No version selected")?;
+
+    Ok(versions.versions[selection].version_number)
 }
 
-pub async fn switch(args: crate::Switch, root: &Path, config: &Config) -> anyhow::Result<()> {
+fn compile_status(versions: &VersionsResponse, version: u32) -> anyhow::Result<CompileStatus> {
+    versions
+        .versions
+        .iter()
+        .find(|vr| vr.version_number == version)
+        .map(|vr| vr.compile_status)
+        .with_context(|| format!("Version {} not found", version))
+}
+
+/// returns `None` when `--dry-run` skipped the actual switch
+pub async fn switch(args: crate::Switch, root: &Path, config: &Config) -> anyhow::Result<Option<SwitchOutcome>> {
+    request::ensure_authenticated(root, config)?;
     let versions = get_versions(root, config).await?;
 
     // Resolve requested version
@@ -107,12 +247,10 @@ pub async fn switch(args: crate::Switch, root: &Path, config: &Config) -> anyhow
         Some(Version::Number(v)) => v,
         Some(Version::Latest) => {
             versions
-                .versions
-                .iter()
-                .map(|vr| vr.version_number)
-                .max()
+                .latest()
                 .context("No versions available to switch to")?
         }
+        None if io::stdin().is_terminal() && io::stdout().is_terminal() => pick_version(&versions)?,
         None => {
             // Show options
             println!("{}", versions);
@@ -130,46 +268,306 @@ pub async fn switch(args: crate::Switch, root: &Path, config: &Config) -> anyhow
                 .and_then(|v| match v {
                     Version::Number(n) => Ok(n),
                     Version::Latest => versions
-                        .versions
-                        .iter()
-                        .map(|vr| vr.version_number)
-                        .max()
+                        .latest()
                         .context("No versions available to switch to"),
                 })?
         }
     };
 
     // Validate existence
-    let version_info = versions
-        .versions
-        .iter()
-        .find(|vr| vr.version_number == version)
-        .with_context(|| format!("Version {} not found", version))?;
+    let mut status = compile_status(&versions, version)?;
+
+    if status == CompileStatus::Pending && args.wait_for_compile {
+        status_inline!("waiting for version {} to finish compiling", version);
+        while status == CompileStatus::Pending {
+            tokio::time::sleep(COMPILE_POLL_INTERVAL).await;
+            chatter_inline!(".");
+            status = compile_status(&get_versions(root, config).await?, version)?;
+        }
+        status!();
+    }
 
     // Validate compile status
-    if !matches!(version_info.compile_status, CompileStatus::Success) {
+    if status != CompileStatus::Success {
         anyhow::bail!(
             "Version {} has status '{}', cannot switch",
             version,
-            version_info.compile_status
+            status
         );
     }
 
     // Send request
-    let client = Client::new();
-    let resp = authenticate(
+    if dry_run::skip(format_args!("switch the active version to {}", version)) {
+        return Ok(None);
+    }
+    let client = request::client(config)?;
+    let req = authenticate(
         root,
+        config,
         client
             .post(format!("{}/bot/change-version", config.api_url))
             .json(&SwitchRequest { version }),
-    )?
-    .send()
-    .await
-    .context("failed to send change-version request")?;
+    )?;
+    let resp = request::send_with_retry(req, request::SEND_ATTEMPTS)
+        .await
+        .context("failed to send change-version request")?;
+    let resp = request::check_status(resp).await?;
+
+    let server_response = resp.text().await.context("failed to read response body")?;
+
+    Ok(Some(SwitchOutcome { version, server_response }))
+}
+
+
+pub async fn show(args: crate::Show, root: &Path, config: &Config) -> anyhow::Result<VersionDetails> {
+    request::ensure_authenticated(root, config)?;
+    let version = match args.version {
+        Version::Number(v) => v,
+        Version::Latest => get_versions(root, config)
+            .await?
+            .latest()
+            .context("No versions available to show")?,
+    };
+
+    let client = request::client(config)?;
+    let req = authenticate(root, config, client.get(format!("{}/bot/versions/{}", config.api_url, version)))?;
+    let resp = request::send_with_retry(req, request::SEND_ATTEMPTS)
+        .await
+        .context("failed to fetch version")?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        anyhow::bail!("Version {} not found", version);
+    }
+
+    request::parse_response(resp).await
+}
+
+/// picks one of the successfully compiled versions at random
+pub async fn random_compiled(root: &Path, config: &Config) -> anyhow::Result<Version> {
+    request::ensure_authenticated(root, config)?;
+    let versions = get_versions(root, config).await?;
+    let compiled: Vec<u32> = versions
+        .versions
+        .iter()
+        .filter(|v| v.compile_status == CompileStatus::Success)
+        .map(|v| v.version_number)
+        .collect();
+    if compiled.is_empty() {
+        anyhow::bail!("No successfully compiled versions to play against");
+    }
 
-    let text = resp.text().await.context("failed to read response body")?;
-    println!("Server response: {}", text);
+    // a fresh v4 uuid is random enough to pick a sparring partner
+    let pick = (uuid::Uuid::new_v4().as_u128() % compiled.len() as u128) as usize;
+    Ok(Version::Number(compiled[pick]))
+}
+
+/// the version a submission became, `None` if the server doesn't say
+pub async fn for_submission(root: &Path, config: &Config, submission_id: u32) -> anyhow::Result<Option<u32>> {
+    Ok(get_versions(root, config)
+        .await?
+        .versions
+        .iter()
+        .find(|v| v.submission_id == Some(submission_id))
+        .map(|v| v.version_number))
+}
+
+/// downloads the compiled bot of a past version into `dir` as an executable,
+/// returns the resolved version number and the bot's path
+pub async fn download_artifact(version: Version, root: &Path, config: &Config, dir: &Path) -> anyhow::Result<(u32, PathBuf)> {
+    request::ensure_authenticated(root, config)?;
+    let versions = get_versions(root, config).await?;
+    let version = match version {
+        Version::Number(v) => v,
+        Version::Latest => versions.latest().context("No versions available to download")?,
+    };
+
+    let status = compile_status(&versions, version)?;
+    if status != CompileStatus::Success {
+        anyhow::bail!("Version {} has status '{}', there is no compiled bot to download", version, status);
+    }
+
+    progress::emit("download", Some(0), &format!("downloading version {}", version));
+    let client = request::transfer_client(config)?;
+    let req = authenticate(root, config, client.get(format!("{}/bot/versions/{}/artifact", config.api_url, version)))?;
+    let resp = request::send_with_retry(req, request::SEND_ATTEMPTS)
+        .await
+        .context("failed to download version")?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        anyhow::bail!("Version {} compiled, but the server has no downloadable bot for it", version);
+    }
+    let resp = request::check_status(resp).await?;
+
+    let path = dir.join(format!("version-{}{}", version, std::env::consts::EXE_SUFFIX));
+    let mut file = std::fs::File::create(&path)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    let total = resp.content_length();
+    let mut downloaded = 0;
+    let mut reported = 0;
+    let mut chunks = resp.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.context("failed to download version")?;
+        file.write_all(&chunk)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        downloaded += chunk.len() as u64;
+        // servers that don't send a length only get the start and end events
+        if let Some(total) = total.filter(|&total| total > 0) {
+            let percent = (downloaded.min(total) * 100 / total) as u8;
+            if percent > reported && percent < 100 {
+                reported = percent;
+                progress::emit("download", Some(percent), &format!("downloading version {}", version));
+            }
+        }
+    }
+    drop(file);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("failed to make {} executable", path.display()))?;
+    }
+
+    progress::emit("download", Some(100), &format!("downloaded version {}", version));
+    Ok((version, path))
+}
+
+/// returns the labeled version, or `None` when `--dry-run` skipped the request
+pub async fn label(args: crate::Label, root: &Path, config: &Config) -> anyhow::Result<Option<u32>> {
+    request::ensure_authenticated(root, config)?;
+    let version = match args.version {
+        Version::Number(v) => v,
+        Version::Latest => get_versions(root, config)
+            .await?
+            .latest()
+            .context("No versions available to label")?,
+    };
+
+    if dry_run::skip(format_args!("label version {} as '{}'", version, args.text)) {
+        return Ok(None);
+    }
+
+    let client = request::client(config)?;
+    let req = authenticate(
+        root,
+        config,
+        client
+            .post(format!("{}/bot/versions/{}/label", config.api_url, version))
+            .json(&LabelRequest { label: args.text }),
+    )?;
+    let resp = request::send_with_retry(req, request::SEND_ATTEMPTS)
+        .await
+        .context("failed to send label request")?;
+    request::check_status(resp).await?;
+
+    Ok(Some(version))
+}
+
+pub struct DeleteOutcome {
+    pub version: u32,
+    pub server_response: String,
+}
+
+/// returns `None` when the user declined or `--dry-run` skipped the request
+pub async fn delete(args: crate::Delete, root: &Path, config: &Config) -> anyhow::Result<Option<DeleteOutcome>> {
+    request::ensure_authenticated(root, config)?;
+    let versions = get_versions(root, config).await?;
+    let version = match args.version {
+        Version::Number(v) => v,
+        Version::Latest => versions.latest().context("No versions available to delete")?,
+    };
+
+    compile_status(&versions, version)?;
+    if versions.active_version == Some(version) {
+        anyhow::bail!(
+            "Version {} is your active version\nswitch to another one first with {}",
+            version,
+            "mm-cli version switch".bold()
+        );
+    }
+
+    if !args.yes {
+        print!("delete version {}? this cannot be undone [y/N] ", version);
+        io::stdout().flush().ok();
+
+        let mut answer = String::new();
+        io::stdin()
+            .read_line(&mut answer)
+            .context("Failed to read input")?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            // on eof the prompt's line was never ended
+            if answer.is_empty() {
+                status!();
+            }
+            status!("not deleted");
+            return Ok(None);
+        }
+    }
+
+    if dry_run::skip(format_args!("delete version {}", version)) {
+        return Ok(None);
+    }
+
+    let client = request::client(config)?;
+    let req = authenticate(root, config, client.delete(format!("{}/bot/versions/{}", config.api_url, version)))?;
+    let resp = request::send_with_retry(req, request::SEND_ATTEMPTS)
+        .await
+        .context("failed to send delete request")?;
+    let resp = request::check_status(resp).await?;
+
+    let server_response = resp.text().await.context("failed to read response body")?;
 
-    Ok(())
+    Ok(Some(DeleteOutcome { version, server_response }))
 }
 
+/// writes every version's metadata to `args.path`, returns how many were written
+/// or `None` when `--dry-run` skipped the write
+pub async fn export(args: crate::Export, root: &Path, config: &Config) -> anyhow::Result<Option<usize>> {
+    request::ensure_authenticated(root, config)?;
+    let versions = get_versions(root, config).await?;
+
+    let format = args.format.unwrap_or_else(|| {
+        match args.path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => ExportFormat::Csv,
+            _ => ExportFormat::Json,
+        }
+    });
+
+    let content = match format {
+        ExportFormat::Json => serde_json::to_string_pretty(&versions)?,
+        ExportFormat::Csv => versions_csv(&versions),
+    };
+
+    if dry_run::skip(format_args!("write {} versions to {}", versions.versions.len(), args.path.display())) {
+        return Ok(None);
+    }
+
+    std::fs::write(&args.path, content)
+        .with_context(|| format!("failed to write {}", args.path.display()))?;
+
+    Ok(Some(versions.versions.len()))
+}
+
+fn versions_csv(versions: &VersionsResponse) -> String {
+    fn field(value: &str) -> String {
+        if value.contains([',', '"', '\n']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+
+    let mut csv = String::from("version,language,compile_status,compiled_at,submitted_at,label,note,active\n");
+    for vr in &versions.versions {
+        let row = [
+            vr.version_number.to_string(),
+            vr.language.clone(),
+            vr.compile_status.to_string(),
+            vr.compiled_at.clone().unwrap_or_default(),
+            vr.submitted_at.clone().unwrap_or_default(),
+            vr.label.clone().unwrap_or_default(),
+            vr.note.clone().unwrap_or_default(),
+            (versions.active_version == Some(vr.version_number)).to_string(),
+        ];
+        csv.push_str(&row.iter().map(|v| field(v)).collect::<Vec<_>>().join(","));
+        csv.push('\n');
+    }
+    csv
+}
@@ -0,0 +1,19 @@
+use std::{fmt::Display, sync::OnceLock};
+
+use colored::Colorize;
+
+static STRICT: OnceLock<bool> = OnceLock::new();
+
+pub fn init(strict: bool) {
+    let _ = STRICT.set(strict);
+}
+
+/// prints a policy warning, or turns it into an error under `--strict`
+pub fn warn(message: impl Display) -> anyhow::Result<()> {
+    if STRICT.get().copied().unwrap_or(false) {
+        anyhow::bail!("{} (failing because of --strict)", message);
+    }
+
+    eprintln!("{}", format!("warning: {}", message).yellow());
+    Ok(())
+}